@@ -11,6 +11,9 @@ pub use sam::*;
 mod gui;
 pub use gui::*;
 
+mod flight;
+pub use flight::*;
+
 impl fmt::Display for Unit {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		write!(f, "{}", match self {
@@ -54,7 +57,13 @@ pub struct VehicleState {
 	pub valve_states: HashMap<String, CompositeValveState>,
 
 	/// Holds the latest readings of all sensors on the vehicle.
+	///
+	/// For sensors with a filter configured in their mapping, this is the filtered reading.
 	pub sensor_readings: HashMap<String, Measurement>,
+
+	/// Holds the latest unfiltered readings of sensors which have a filter configured.
+	#[serde(default)]
+	pub raw_sensor_readings: HashMap<String, Measurement>,
 }
 
 impl VehicleState {
@@ -63,10 +72,17 @@ impl VehicleState {
 		VehicleState {
 			valve_states: HashMap::new(),
 			sensor_readings: HashMap::new(),
+			raw_sensor_readings: HashMap::new(),
 		}
 	}
 }
 
+impl Default for VehicleState {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 /// Used in a `NodeMapping` to determine which computer the action should be send to.
 #[derive(Clone, Copy, Debug, Deserialize, Eq, MaxSize, PartialEq, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -112,6 +128,12 @@ impl FromSql for Computer {
 }
 
 /// The mapping of an individual node.
+///
+/// The `#[serde(default)]` fields below may be omitted from self-describing formats such as JSON, but
+/// postcard encodes every field positionally, so adding `expression` and `filter` changed the wire
+/// format: mappings encoded without them fail to decode, and the reverse. The control server and the
+/// flight computer must therefore be built from the same version, which also keeps their
+/// `MappingFingerprint`s comparable, since those hash the postcard encoding.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct NodeMapping {
 	/// The text identifier, or name, of the node.
@@ -155,9 +177,106 @@ pub struct NodeMapping {
 
 	/// Indicator of whether the valve is normally open or normally closed.
 	pub normally_closed: Option<bool>,
+
+	/// The arithmetic expression over other mapped sensors which defines the value of the node,
+	/// such as `FUPT - CCPT` or `LC1 + LC2 + LC3`, whose result must have a unit.
+	/// This is only used for sensors with sensor type Virtual.
+	#[serde(default)]
+	pub expression: Option<String>,
+
+	/// The digital filter applied to readings of the sensor during ingest, if any.
+	#[serde(default)]
+	pub filter: Option<FilterConfig>,
+}
+
+/// Configuration of a digital filter applied to the readings of a single sensor.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterConfig {
+	/// Averages the last `window` readings.
+	MovingAverage {
+		/// The number of readings averaged together.
+		window: usize,
+	},
+
+	/// Exponential moving average, weighting each new reading by `alpha` between 0 and 1.
+	Exponential {
+		/// The weight of the newest reading, where 1 disables filtering entirely.
+		alpha: f64,
+	},
+
+	/// First-order low-pass filter which uses reading timestamps to attenuate frequencies above the cutoff.
+	LowPass {
+		/// The cutoff frequency, in hertz.
+		cutoff: f64,
+	},
+
+	/// Takes the median of the last `window` readings, which rejects individual spikes.
+	Median {
+		/// The number of readings the median is taken over.
+		window: usize,
+	},
+}
+
+/// A stable, order-independent content hash of a set of node mappings.
+///
+/// The control server computes this over the mappings it last sent and compares it against the one
+/// reported by the flight computer to detect when the two have drifted apart. The hash only depends
+/// on the contents of each mapping, so the order of the `Vec<NodeMapping>` does not matter, and it is
+/// stable across builds and machines because it is computed over the postcard encoding of each mapping.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, MaxSize, PartialEq, Serialize)]
+pub struct MappingFingerprint(pub u64);
+
+impl MappingFingerprint {
+	/// Computes the fingerprint of the given set of mappings.
+	pub fn of(mappings: &[NodeMapping]) -> Self {
+		let mut hashes = mappings
+			.iter()
+			.map(|mapping| {
+				let bytes = postcard::to_allocvec(mapping)
+					.expect("failed to serialize NodeMapping with postcard (this should not be possible)");
+
+				fnv1a(&bytes)
+			})
+			.collect::<Vec<u64>>();
+
+		// sorting the individual hashes is what makes the fingerprint independent of mapping order,
+		// while still distinguishing sets which differ only by a duplicated mapping (unlike XOR).
+		hashes.sort_unstable();
+
+		let bytes = hashes
+			.iter()
+			.flat_map(|hash| hash.to_le_bytes())
+			.collect::<Vec<u8>>();
+
+		MappingFingerprint(fnv1a(&bytes))
+	}
+}
+
+impl fmt::Display for MappingFingerprint {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:016x}", self.0)
+	}
+}
+
+/// Computes the 64-bit FNV-1a hash of the given bytes.
+///
+/// Used instead of `std::hash` where the hash is sent across the network or stored, since the
+/// standard library makes no guarantee that its hashers are stable between releases.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+	const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+	const PRIME: u64 = 0x100000001b3;
+
+	bytes.iter().fold(OFFSET_BASIS, |hash, byte| (hash ^ *byte as u64).wrapping_mul(PRIME))
 }
 
 /// A sequence written in Python, used by the flight computer to execute arbitrary operator code.
+///
+/// As with `NodeMapping`, the `#[serde(default)]` fields only help self-describing formats. Postcard
+/// encodes fields positionally, so adding `parameters` and `arguments` changed the wire format of every
+/// message carrying a sequence, and sequences encoded without them fail to decode. The GUI, control
+/// server and flight computer must be built from the same version. The `AbortStore` only persists the
+/// script, so a stored abort sequence survives the change but loses its parameters.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Sequence {
 	/// The unique, human-readable name which identifies the sequence.
@@ -168,9 +287,88 @@ pub struct Sequence {
 
 	/// The script run immediately (except abort) upon being received. 
 	pub script: String,
+
+	/// The parameters of the script, each defined as a global before the script runs.
+	#[serde(default)]
+	pub parameters: Vec<Parameter>,
+
+	/// The values given to the parameters for this run, by parameter name. A parameter without an
+	/// argument takes its default value.
+	#[serde(default)]
+	pub arguments: HashMap<String, ParameterValue>,
 }
 
-/// A trigger with a
+/// A typed parameter declared by a sequence, so that one script can be reused with different values.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Parameter {
+	/// The name of the global the value is given to, which must be a valid Python identifier other
+	/// than a keyword, and must not shadow a mapping, an export of the `sequences` library or a builtin.
+	pub name: String,
+
+	/// The type of value the parameter takes.
+	pub kind: ParameterKind,
+
+	/// The value taken when no argument is given, or `None` if an argument is required.
+	#[serde(default)]
+	pub default: Option<ParameterValue>,
+}
+
+/// The type of value a sequence parameter takes.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterKind {
+	/// A `bool`.
+	Boolean,
+
+	/// An `int`.
+	Integer,
+
+	/// A `float`, which may also be given as an integer.
+	Number,
+
+	/// A `str`.
+	Text,
+
+	/// A quantity in the contained unit, such as a `Pressure` for `Unit::Psi`, which is given as a
+	/// number in that unit.
+	Quantity(Unit),
+}
+
+impl fmt::Display for ParameterKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Boolean => write!(f, "boolean"),
+			Self::Integer => write!(f, "integer"),
+			Self::Number => write!(f, "number"),
+			Self::Text => write!(f, "text"),
+			Self::Quantity(unit) => write!(f, "quantity in {unit}"),
+		}
+	}
+}
+
+/// The value of a sequence parameter.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterValue {
+	/// A boolean value.
+	Boolean(bool),
+
+	/// An integer value.
+	Integer(i64),
+
+	/// A real number, or the magnitude of a quantity.
+	Number(f64),
+
+	/// A string value.
+	Text(String),
+}
+
+/// A trigger which runs a script whenever its condition is met.
+///
+/// As with `NodeMapping`, the `#[serde(default)]` fields only help self-describing formats. Postcard
+/// encodes fields positionally, so adding `mode`, `cooldown` and `priority` changed the wire format of
+/// every message carrying a trigger, and triggers encoded without them fail to decode. The GUI, control
+/// server and flight computer must be built from the same version.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Trigger {
 	/// The unique, human-readable name which identifies the trigger.
@@ -184,6 +382,77 @@ pub struct Trigger {
 
 	/// Whether or not the trigger is active
 	pub active: bool,
+
+	/// Determines when a met condition causes the script to run.
+	#[serde(default)]
+	pub mode: TriggerMode,
+
+	/// The minimum time between two runs of the script, if any.
+	#[serde(default)]
+	pub cooldown: Option<std::time::Duration>,
+
+	/// The priority of the trigger, where triggers with higher priorities have their scripts started
+	/// first when several conditions are met at once.
+	#[serde(default)]
+	pub priority: i32,
+}
+
+/// Determines when a met trigger condition causes the trigger script to run.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, MaxSize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerMode {
+	/// Runs the script only when the condition changes from not met to met.
+	#[default]
+	RisingEdge,
+
+	/// Runs the script every time the condition is checked and met.
+	Level,
+
+	/// Runs the script the first time the condition is met, then deactivates the trigger.
+	OneShot,
+}
+
+/// A problem found in a sequence by static validation, before it is run.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Diagnostic {
+	/// The line of the script at which the problem was found, starting at 1, or 0 if the problem is
+	/// not in the script itself, such as in the mappings it is checked against.
+	pub line: u32,
+
+	/// The column of the line at which the problem was found, starting at 1, or 0 along with the line.
+	pub column: u32,
+
+	/// How severe the problem is.
+	pub severity: Severity,
+
+	/// A human-readable description of the problem.
+	pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}: {}: {}", self.line, self.column, self.severity, self.message)
+	}
+}
+
+/// The severity of a `Diagnostic`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, MaxSize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+	/// The script will certainly fail when run, such as when referencing an undefined name.
+	Error,
+
+	/// The script is likely to fail when run, such as when comparing quantities of different units.
+	Warning,
+}
+
+impl fmt::Display for Severity {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Error => write!(f, "error"),
+			Self::Warning => write!(f, "warning"),
+		}
+	}
 }
 
 /// A message sent from the control server to the flight computer.
@@ -203,6 +472,10 @@ pub enum FlightControlMessage {
 
 	/// Instructs the flight computer to run an immediate abort.
 	Abort,
+
+	/// Requests that the flight computer report the fingerprint of its active mappings with
+	/// `FlightMessage::MappingFingerprint`.
+	RequestMappingFingerprint,
 }
 
 // #[cfg(test)]
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, time::Duration};
+use super::MappingFingerprint;
+
+/// A message sent from the flight computer to the control server.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum FlightMessage {
+	/// The fingerprint of the mappings currently active on the flight computer.
+	///
+	/// Sent in response to `FlightControlMessage::RequestMappingFingerprint` and after applying a new
+	/// set of mappings, so the server can compare it against `MappingFingerprint::of` the mappings it
+	/// last sent and have the GUI flag a mismatch.
+	MappingFingerprint(MappingFingerprint),
+
+	/// Reports that the sequence with the given name did not run to completion.
+	SequenceFailed {
+		/// The name of the sequence which failed.
+		name: String,
+
+		/// The reason for the failure.
+		error: SequenceError,
+	},
+
+	/// A message logged by a running sequence, to be forwarded to the GUI.
+	SequenceLog(LogRecord),
+}
+
+/// The severity of a message logged by a sequence.
+#[cfg_attr(feature = "sequences", pyo3::pyclass)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+	/// Detailed information only useful while developing a sequence.
+	Debug,
+
+	/// Routine progress of a sequence, such as anything it prints to stdout.
+	Info,
+
+	/// Something unexpected which the sequence can continue past.
+	Warning,
+
+	/// A failure, such as anything a sequence writes to stderr.
+	Error,
+}
+
+impl fmt::Display for LogLevel {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Debug => write!(f, "debug"),
+			Self::Info => write!(f, "info"),
+			Self::Warning => write!(f, "warning"),
+			Self::Error => write!(f, "error"),
+		}
+	}
+}
+
+/// A message logged by a sequence, either through `log` or by writing a line to stdout or stderr.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LogRecord {
+	/// The name of the sequence which logged the message.
+	pub sequence: String,
+
+	/// When the message was logged, as a UNIX timestamp in seconds.
+	pub timestamp: f64,
+
+	/// The severity of the message.
+	pub level: LogLevel,
+
+	/// The text of the message, without a trailing newline.
+	pub message: String,
+}
+
+/// The details of a Python exception raised while running a sequence.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PythonException {
+	/// The name of the exception type, such as `NameError`.
+	pub exception_type: String,
+
+	/// The message of the exception.
+	pub message: String,
+
+	/// The formatted Python traceback, if one was available.
+	pub traceback: Option<String>,
+
+	/// The line of the sequence script at which the exception was raised, if known.
+	pub line: Option<u32>,
+}
+
+impl fmt::Display for PythonException {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {}", self.exception_type, self.message)?;
+
+		if let Some(line) = self.line {
+			write!(f, " (line {line})")?;
+		}
+
+		Ok(())
+	}
+}
+
+/// The reason a sequence failed to run to completion.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SequenceError {
+	/// The sequences library was not initialized before running the sequence.
+	Uninitialized,
+
+	/// The `sequences` Python module could not be imported.
+	Import(PythonException),
+
+	/// An argument given to the sequence did not match its parameters, so the script was not run.
+	InvalidArgument {
+		/// The name of the parameter or argument at fault.
+		parameter: String,
+
+		/// Why the argument is invalid.
+		reason: String,
+	},
+
+	/// A mapping could not be defined as a Python object before running the script.
+	Mapping {
+		/// The text ID of the mapping which could not be defined.
+		text_id: String,
+
+		/// The exception raised while defining it.
+		exception: PythonException,
+	},
+
+	/// The script raised an exception which it did not handle.
+	Exception(PythonException),
+
+	/// The sequence was interrupted by an abort.
+	Aborted,
+
+	/// The sequence was stopped before finishing, such as by `FlightControlMessage::StopSequence`.
+	Stopped,
+
+	/// The sequence was not started because another sequence with the same name is already running.
+	AlreadyRunning(String),
+
+	/// The sequence was interrupted by the watchdog for running longer than its budget without waiting.
+	Overrun {
+		/// The budget which the sequence overran.
+		budget: Duration,
+	},
+}
+
+impl fmt::Display for SequenceError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Uninitialized => write!(f, "sequences library has not been initialized"),
+			Self::Import(exception) => write!(f, "failed to import sequences library: {exception}"),
+			Self::InvalidArgument { parameter, reason } => write!(f, "invalid argument for parameter '{parameter}': {reason}"),
+			Self::Mapping { text_id, exception } => write!(f, "failed to define '{text_id}' as a mapping: {exception}"),
+			Self::Exception(exception) => write!(f, "{exception}"),
+			Self::Aborted => write!(f, "sequence was aborted"),
+			Self::Stopped => write!(f, "sequence was stopped"),
+			Self::AlreadyRunning(name) => write!(f, "a sequence named '{name}' is already running"),
+			Self::Overrun { budget } => write!(f, "sequence ran for longer than its budget of {budget:?} without waiting"),
+		}
+	}
+}
+
+impl Error for SequenceError {}
+
+/// The status of a sequence known to the flight computer.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SequenceStatus {
+	/// The sequence is currently running.
+	Running,
+
+	/// The sequence ran to completion.
+	Finished,
+
+	/// The sequence did not run to completion for the contained reason.
+	Failed(SequenceError),
+
+	/// The sequence was stopped before finishing.
+	Stopped,
+}
+
+impl From<Result<(), SequenceError>> for SequenceStatus {
+	fn from(result: Result<(), SequenceError>) -> Self {
+		match result {
+			Ok(()) => SequenceStatus::Finished,
+			Err(SequenceError::Stopped) => SequenceStatus::Stopped,
+			Err(error) => SequenceStatus::Failed(error),
+		}
+	}
+}
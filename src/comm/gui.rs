@@ -8,6 +8,7 @@ use super::ChannelType;
 use rusqlite::{ToSql, types::{FromSqlError, FromSqlResult, ToSqlOutput, FromSql, Value as SqlValue, ValueRef as SqlValueRef}};
 
 /// The state or commanded state of a valve.
+#[cfg_attr(feature = "sequences", pyo3::pyclass)]
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, MaxSize, PartialEq, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ValveState {
@@ -60,6 +61,7 @@ impl rusqlite::ToSql for ValveState {
 }
 
 /// Stores the estimated actual valve state as well as the software-commanded state.
+#[cfg_attr(feature = "sequences", pyo3::pyclass(get_all))]
 #[derive(Clone, Debug, Deserialize, Eq, Hash, MaxSize, PartialEq, Serialize)]
 pub struct CompositeValveState {
 	/// Commanded state of the valve, according to software.
@@ -93,6 +95,9 @@ pub enum SensorType {
 
 	/// Valve, which can be actuated and read with voltage and current.
 	Valve,
+
+	/// Virtual sensor, whose value is computed from other sensors using the mapping's expression.
+	Virtual,
 }
 
 impl SensorType {
@@ -106,6 +111,7 @@ impl SensorType {
 			Self::Rtd => &[ChannelType::Rtd],
 			Self::Tc => &[ChannelType::Tc],
 			Self::Valve => &[ChannelType::ValveVoltage, ChannelType::ValveCurrent],
+			Self::Virtual => &[],
 		}
 	}
 }
@@ -120,6 +126,7 @@ impl fmt::Display for SensorType {
 			Self::Rtd => write!(f, "rtd"),
 			Self::Tc => write!(f, "tc"),
 			Self::Valve => write!(f, "valve"),
+			Self::Virtual => write!(f, "virtual"),
 		}
 	}
 }
@@ -136,6 +143,7 @@ impl FromStr for SensorType {
 			"rtd" => Ok(Self::Rtd),
 			"tc" => Ok(Self::Tc),
 			"valve" => Ok(Self::Valve),
+			"virtual" => Ok(Self::Virtual),
 			_ => Err(()),
 		}
 	}
@@ -189,3 +189,47 @@ pub enum DataMessage<'a> {
 	/// Data originating from the BMS.
 	Bms(BoardId),
 }
+
+/// Summary statistics of a board's power rail measurements.
+#[derive(Clone, Copy, Debug, Deserialize, MaxSize, PartialEq, Serialize)]
+pub struct RailStatistics {
+	/// The most recent measurement.
+	pub latest: f64,
+
+	/// The lowest measurement seen since the board was first heard from.
+	pub min: f64,
+
+	/// The highest measurement seen since the board was first heard from.
+	pub max: f64,
+}
+
+/// A report of the health of a single data board, built from its `DataMessage` traffic.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BoardHealth {
+	/// The ID of the board which the report describes.
+	pub board_id: BoardId,
+
+	/// Statistics of the rail voltage, in volts, if the board has reported any.
+	pub rail_voltage: Option<RailStatistics>,
+
+	/// Statistics of the rail current, in amps, if the board has reported any.
+	pub rail_current: Option<RailStatistics>,
+
+	/// Whether the latest rail voltage and current are both within their configured limits.
+	pub rails_in_range: bool,
+
+	/// Whether the board is currently in a brownout, with its rail voltage below the brownout threshold.
+	pub brownout: bool,
+
+	/// The number of separate brownouts detected since the board was first heard from.
+	pub brownout_count: u32,
+
+	/// The UNIX timestamp of the last message received from the board.
+	pub last_seen: f64,
+
+	/// Whether a message has been received from the board within the configured timeout.
+	pub online: bool,
+
+	/// The rate at which messages have recently been received from the board, in messages per second.
+	pub packet_rate: f64,
+}
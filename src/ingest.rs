@@ -0,0 +1,8 @@
+mod expression;
+pub use expression::*;
+
+mod filter;
+pub use filter::*;
+
+mod health;
+pub use health::*;
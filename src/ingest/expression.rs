@@ -0,0 +1,445 @@
+use crate::comm::{Measurement, NodeMapping, SensorType, Unit, VehicleState};
+use std::{collections::{HashMap, HashSet}, error::Error, fmt, iter::Peekable, str::Chars};
+
+/// The deepest an expression may nest, counting parentheses, negations and chained operators, so
+/// that parsing and evaluating an expression cannot overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/// An error encountered while parsing or checking the expression of a virtual sensor.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExpressionError {
+	/// The expression could not be parsed, with a description of what went wrong.
+	Syntax(String),
+
+	/// A virtual mapping has no expression defined.
+	MissingExpression(String),
+
+	/// The expression references a name which is not a mapped sensor.
+	UnknownSensor {
+		/// The text ID of the virtual mapping containing the reference.
+		mapping: String,
+
+		/// The unknown name.
+		reference: String,
+	},
+
+	/// The expression combines quantities whose units are incompatible, such as adding psi to lbf.
+	UnitMismatch {
+		/// The text ID of the virtual mapping containing the expression.
+		mapping: String,
+
+		/// A description of the offending operation.
+		reason: String,
+	},
+
+	/// Virtual sensors reference each other in a cycle, so none of them can be evaluated.
+	Cycle(String),
+}
+
+impl fmt::Display for ExpressionError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Syntax(reason) => write!(f, "syntax error: {reason}"),
+			Self::MissingExpression(mapping) => write!(f, "virtual sensor '{mapping}' has no expression"),
+			Self::UnknownSensor { mapping, reference } => write!(f, "virtual sensor '{mapping}' references unknown sensor '{reference}'"),
+			Self::UnitMismatch { mapping, reason } => write!(f, "virtual sensor '{mapping}' has mismatched units: {reason}"),
+			Self::Cycle(mapping) => write!(f, "virtual sensor '{mapping}' depends on itself"),
+		}
+	}
+}
+
+impl Error for ExpressionError {}
+
+/// A binary arithmetic operator usable in an expression.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operator {
+	/// Addition, `+`.
+	Add,
+
+	/// Subtraction, `-`.
+	Subtract,
+
+	/// Multiplication, `*`.
+	Multiply,
+
+	/// Division, `/`.
+	Divide,
+}
+
+impl fmt::Display for Operator {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", match self {
+			Self::Add => "+",
+			Self::Subtract => "-",
+			Self::Multiply => "*",
+			Self::Divide => "/",
+		})
+	}
+}
+
+/// A parsed arithmetic expression over sensor readings, such as `FUPT - CCPT`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+	/// A unitless numeric literal.
+	Constant(f64),
+
+	/// The latest reading of the sensor with the given text ID.
+	Sensor(String),
+
+	/// The negation of the inner expression.
+	Negate(Box<Expression>),
+
+	/// A binary operation on two expressions.
+	Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+	/// Parses an expression from a string containing numbers, sensor names, `+ - * /` and parentheses.
+	///
+	/// Numbers may have a fractional part and an exponent, such as `2.5e-4`. Expressions nested more
+	/// than 64 levels deep are rejected.
+	pub fn parse(source: &str) -> Result<Self, ExpressionError> {
+		let mut parser = Parser { chars: source.chars().peekable(), depth: 0 };
+		let expression = parser.sum()?;
+
+		parser.skip_whitespace();
+
+		if let Some(c) = parser.chars.peek() {
+			return Err(ExpressionError::Syntax(format!("unexpected character '{c}'")));
+		}
+
+		Ok(expression)
+	}
+
+	/// Collects the names of all sensors referenced in the expression.
+	pub fn references(&self) -> HashSet<&str> {
+		let mut references = HashSet::new();
+		self.collect_references(&mut references);
+		references
+	}
+
+	fn collect_references<'a>(&'a self, references: &mut HashSet<&'a str>) {
+		match self {
+			Self::Constant(_) => {},
+			Self::Sensor(name) => { references.insert(name); },
+			Self::Negate(inner) => inner.collect_references(references),
+			Self::Binary(_, left, right) => {
+				left.collect_references(references);
+				right.collect_references(references);
+			},
+		}
+	}
+
+	/// Evaluates the expression against the given sensor readings.
+	///
+	/// Returns `None` if any referenced sensor has no reading yet. The result may be infinite or NaN,
+	/// such as after dividing by a reading of zero.
+	pub fn evaluate(&self, readings: &HashMap<String, Measurement>) -> Option<f64> {
+		match self {
+			Self::Constant(value) => Some(*value),
+			Self::Sensor(name) => readings.get(name).map(|measurement| measurement.value),
+			Self::Negate(inner) => inner.evaluate(readings).map(|value| -value),
+			Self::Binary(operator, left, right) => {
+				let left = left.evaluate(readings)?;
+				let right = right.evaluate(readings)?;
+
+				Some(match operator {
+					Operator::Add => left + right,
+					Operator::Subtract => left - right,
+					Operator::Multiply => left * right,
+					Operator::Divide => left / right,
+				})
+			},
+		}
+	}
+
+	/// Determines the unit of the result given the units of referenced sensors, following the same rules
+	/// as the unit types in sequences: only like units may be added or subtracted, and units may only be
+	/// scaled by unitless values. Dividing like units yields a unitless ratio, which may be scaled or
+	/// combined with constants but cannot itself be the value of a virtual sensor.
+	fn unit(&self, units: &HashMap<&str, Unit>) -> Result<Option<Unit>, String> {
+		match self {
+			Self::Constant(_) => Ok(None),
+			Self::Sensor(name) => units
+				.get(name.as_str())
+				.map(|unit| Some(*unit))
+				.ok_or_else(|| name.clone()),
+			Self::Negate(inner) => inner.unit(units),
+			Self::Binary(operator, left, right) => {
+				let left = left.unit(units)?;
+				let right = right.unit(units)?;
+
+				match (operator, left, right) {
+					(Operator::Add | Operator::Subtract, left, right) if left == right => Ok(left),
+					(Operator::Multiply, unit, None) | (Operator::Multiply, None, unit) => Ok(unit),
+					(Operator::Divide, unit, None) => Ok(unit),
+					(Operator::Divide, Some(left), Some(right)) if left == right => Ok(None),
+					(operator, left, right) => Err(format!(
+						"cannot apply '{operator}' to {} and {}",
+						left.map_or("a unitless value".to_owned(), |unit| unit.to_string()),
+						right.map_or("a unitless value".to_owned(), |unit| unit.to_string()),
+					)),
+				}
+			},
+		}
+	}
+}
+
+struct Parser<'a> {
+	chars: Peekable<Chars<'a>>,
+	depth: usize,
+}
+
+impl Parser<'_> {
+	fn skip_whitespace(&mut self) {
+		while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+	}
+
+	/// Enters one more level of nesting, failing if the expression is nested too deeply.
+	fn descend(&mut self) -> Result<(), ExpressionError> {
+		if self.depth >= MAX_DEPTH {
+			return Err(ExpressionError::Syntax(format!("expression is nested more than {MAX_DEPTH} levels deep")));
+		}
+
+		self.depth += 1;
+		Ok(())
+	}
+
+	fn sum(&mut self) -> Result<Expression, ExpressionError> {
+		let depth = self.depth;
+		let expression = self.chain(Self::product, |c| match c {
+			'+' => Some(Operator::Add),
+			'-' => Some(Operator::Subtract),
+			_ => None,
+		});
+
+		self.depth = depth;
+		expression
+	}
+
+	fn product(&mut self) -> Result<Expression, ExpressionError> {
+		let depth = self.depth;
+		let expression = self.chain(Self::unary, |c| match c {
+			'*' => Some(Operator::Multiply),
+			'/' => Some(Operator::Divide),
+			_ => None,
+		});
+
+		self.depth = depth;
+		expression
+	}
+
+	/// Parses operands joined by left-associative operators, where each operator nests the operands
+	/// before it one level deeper.
+	fn chain(
+		&mut self,
+		operand: fn(&mut Self) -> Result<Expression, ExpressionError>,
+		operator: fn(char) -> Option<Operator>,
+	) -> Result<Expression, ExpressionError> {
+		let mut expression = operand(self)?;
+
+		loop {
+			self.skip_whitespace();
+
+			let Some(operator) = self.chars.peek().and_then(|c| operator(*c)) else {
+				return Ok(expression);
+			};
+
+			self.chars.next();
+			self.descend()?;
+			expression = Expression::Binary(operator, Box::new(expression), Box::new(operand(self)?));
+		}
+	}
+
+	fn unary(&mut self) -> Result<Expression, ExpressionError> {
+		self.skip_whitespace();
+
+		match self.chars.peek() {
+			Some('-') => {
+				self.chars.next();
+				self.descend()?;
+
+				let inner = self.unary()?;
+				self.depth -= 1;
+
+				Ok(Expression::Negate(Box::new(inner)))
+			},
+			Some('(') => {
+				self.chars.next();
+				self.descend()?;
+
+				let expression = self.sum()?;
+				self.depth -= 1;
+				self.skip_whitespace();
+
+				if self.chars.next() != Some(')') {
+					return Err(ExpressionError::Syntax("expected ')'".to_owned()));
+				}
+
+				Ok(expression)
+			},
+			Some(c) if c.is_ascii_digit() || *c == '.' => self.number(),
+			Some(c) if c.is_alphabetic() || *c == '_' => {
+				let mut name = String::new();
+
+				while let Some(c) = self.chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+					name.push(c);
+				}
+
+				Ok(Expression::Sensor(name))
+			},
+			Some(c) => Err(ExpressionError::Syntax(format!("unexpected character '{c}'"))),
+			None => Err(ExpressionError::Syntax("unexpected end of expression".to_owned())),
+		}
+	}
+
+	/// Lexes a number literal with an optional fractional part and exponent, such as `2.5e-4`.
+	fn number(&mut self) -> Result<Expression, ExpressionError> {
+		let mut literal = String::new();
+
+		while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+			if c == '.' && literal.contains('.') {
+				return Err(ExpressionError::Syntax(format!("number '{literal}.' has more than one decimal point")));
+			}
+
+			literal.push(c);
+		}
+
+		if let Some(e) = self.chars.next_if(|c| *c == 'e' || *c == 'E') {
+			literal.push(e);
+
+			if let Some(sign) = self.chars.next_if(|c| *c == '+' || *c == '-') {
+				literal.push(sign);
+			}
+
+			let digits = literal.len();
+
+			while let Some(c) = self.chars.next_if(char::is_ascii_digit) {
+				literal.push(c);
+			}
+
+			if literal.len() == digits {
+				return Err(ExpressionError::Syntax(format!("exponent of number '{literal}' has no digits")));
+			}
+		}
+
+		literal
+			.parse()
+			.map(Expression::Constant)
+			.map_err(|_| ExpressionError::Syntax(format!("invalid number '{literal}'")))
+	}
+}
+
+/// The compiled set of virtual sensors defined by a set of mappings.
+///
+/// Expressions are parsed and unit-checked once when compiled, then evaluated in dependency order
+/// on every ingest so that virtual sensors may be defined in terms of other virtual sensors.
+///
+/// Every reading carries a `Unit`, none of which is dimensionless, so the result of an expression
+/// must have a unit. Unitless results, such as the ratio of two pressures, are rejected when compiled.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VirtualSensors {
+	sensors: Vec<(String, Expression, Unit)>,
+}
+
+impl VirtualSensors {
+	/// Compiles the expressions of all virtual mappings in the given set.
+	pub fn compile(mappings: &[NodeMapping]) -> Result<Self, ExpressionError> {
+		let mut units = mappings
+			.iter()
+			.filter_map(|mapping| {
+				let unit = match mapping.sensor_type {
+					SensorType::Valve | SensorType::Virtual => return None,
+					sensor_type => sensor_type.channel_types()[0].unit(),
+				};
+
+				Some((mapping.text_id.as_str(), unit))
+			})
+			.collect::<HashMap<&str, Unit>>();
+
+		let mut pending = mappings
+			.iter()
+			.filter(|mapping| mapping.sensor_type == SensorType::Virtual)
+			.map(|mapping| {
+				let source = mapping.expression
+					.as_deref()
+					.ok_or_else(|| ExpressionError::MissingExpression(mapping.text_id.clone()))?;
+
+				Ok((mapping.text_id.as_str(), Expression::parse(source)?))
+			})
+			.collect::<Result<Vec<_>, ExpressionError>>()?;
+
+		let virtual_names = pending
+			.iter()
+			.map(|(name, _)| *name)
+			.collect::<HashSet<&str>>();
+
+		for (name, expression) in &pending {
+			if let Some(reference) = expression
+				.references()
+				.into_iter()
+				.find(|reference| !units.contains_key(reference) && !virtual_names.contains(reference))
+			{
+				return Err(ExpressionError::UnknownSensor {
+					mapping: name.to_string(),
+					reference: reference.to_owned(),
+				});
+			}
+		}
+
+		let mut sensors = Vec::with_capacity(pending.len());
+
+		// repeatedly resolve every virtual sensor whose references all have known units, which yields
+		// a valid evaluation order. if a pass makes no progress, the remaining sensors form a cycle.
+		while !pending.is_empty() {
+			let (ready, blocked): (Vec<_>, Vec<_>) = pending
+				.into_iter()
+				.partition(|(_, expression)| expression.references().iter().all(|reference| units.contains_key(reference)));
+
+			if ready.is_empty() {
+				return Err(ExpressionError::Cycle(blocked[0].0.to_owned()));
+			}
+
+			for (name, expression) in ready {
+				let unit = expression
+					.unit(&units)
+					.map_err(|reason| ExpressionError::UnitMismatch { mapping: name.to_owned(), reason })?
+					.ok_or_else(|| ExpressionError::UnitMismatch {
+						mapping: name.to_owned(),
+						reason: "result has no unit, and unitless virtual sensors are not supported".to_owned(),
+					})?;
+
+				units.insert(name, unit);
+				sensors.push((name.to_owned(), expression, unit));
+			}
+
+			pending = blocked;
+		}
+
+		Ok(VirtualSensors { sensors })
+	}
+
+	/// Gets the unit of the virtual sensor with the given name, inferred from its expression.
+	pub fn unit(&self, name: &str) -> Option<Unit> {
+		self.sensors
+			.iter()
+			.find(|(sensor, _, _)| sensor == name)
+			.map(|(_, _, unit)| *unit)
+	}
+
+	/// Evaluates every virtual sensor against the vehicle state and stores the results in its sensor readings.
+	///
+	/// Virtual sensors which reference a sensor without a reading, or whose result is not finite, such
+	/// as after dividing by a reading of zero, are left untouched.
+	pub fn evaluate(&self, state: &mut VehicleState) {
+		for (name, expression, unit) in &self.sensors {
+			let value = expression
+				.evaluate(&state.sensor_readings)
+				.filter(|value| value.is_finite());
+
+			if let Some(value) = value {
+				state.sensor_readings.insert(name.clone(), Measurement { value, unit: *unit });
+			}
+		}
+	}
+}
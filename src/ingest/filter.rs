@@ -0,0 +1,205 @@
+use crate::comm::{FilterConfig, Measurement, NodeMapping, VehicleState};
+use std::{collections::{HashMap, VecDeque}, error::Error, f64::consts::PI, fmt};
+
+/// An error in the filter configured for a sensor.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterError {
+	/// A moving average or median filter has a window of zero readings.
+	EmptyWindow {
+		/// The text ID of the mapping with the filter.
+		mapping: String,
+	},
+
+	/// An exponential filter has a weight outside of the range `0 < alpha <= 1`.
+	InvalidAlpha {
+		/// The text ID of the mapping with the filter.
+		mapping: String,
+
+		/// The configured weight.
+		alpha: f64,
+	},
+
+	/// A low-pass filter has a cutoff frequency which is not a positive, finite number of hertz.
+	InvalidCutoff {
+		/// The text ID of the mapping with the filter.
+		mapping: String,
+
+		/// The configured cutoff frequency.
+		cutoff: f64,
+	},
+}
+
+impl fmt::Display for FilterError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::EmptyWindow { mapping } => write!(f, "filter of '{mapping}' has an empty window"),
+			Self::InvalidAlpha { mapping, alpha } => write!(f, "filter of '{mapping}' has alpha {alpha}, which is not in (0, 1]"),
+			Self::InvalidCutoff { mapping, cutoff } => write!(f, "filter of '{mapping}' has cutoff {cutoff} Hz, which is not positive"),
+		}
+	}
+}
+
+impl Error for FilterError {}
+
+/// The running state of a single configured filter.
+#[derive(Clone, Debug)]
+enum FilterState {
+	MovingAverage { window: usize, values: VecDeque<f64>, sum: f64 },
+	Exponential { alpha: f64, output: Option<f64> },
+	LowPass { time_constant: f64, last: Option<(f64, f64)> },
+	Median { window: usize, values: VecDeque<f64> },
+}
+
+impl FilterState {
+	fn new(mapping: &str, config: FilterConfig) -> Result<Self, FilterError> {
+		let filter = match config {
+			FilterConfig::MovingAverage { window } | FilterConfig::Median { window } if window == 0 => {
+				return Err(FilterError::EmptyWindow { mapping: mapping.to_owned() });
+			},
+			FilterConfig::Exponential { alpha } if !(alpha > 0.0 && alpha <= 1.0) => {
+				return Err(FilterError::InvalidAlpha { mapping: mapping.to_owned(), alpha });
+			},
+			FilterConfig::LowPass { cutoff } if !(cutoff > 0.0 && cutoff.is_finite()) => {
+				return Err(FilterError::InvalidCutoff { mapping: mapping.to_owned(), cutoff });
+			},
+			FilterConfig::MovingAverage { window } => FilterState::MovingAverage {
+				window,
+				values: VecDeque::new(),
+				sum: 0.0,
+			},
+			FilterConfig::Exponential { alpha } => FilterState::Exponential {
+				alpha,
+				output: None,
+			},
+			FilterConfig::LowPass { cutoff } => FilterState::LowPass {
+				time_constant: 1.0 / (2.0 * PI * cutoff),
+				last: None,
+			},
+			FilterConfig::Median { window } => FilterState::Median {
+				window,
+				values: VecDeque::new(),
+			},
+		};
+
+		Ok(filter)
+	}
+
+	fn apply(&mut self, value: f64, timestamp: f64) -> f64 {
+		// a single NaN would otherwise stay in the running sum or output of the filter indefinitely
+		if !value.is_finite() {
+			return value;
+		}
+
+		match self {
+			Self::MovingAverage { window, values, sum } => {
+				values.push_back(value);
+				*sum += value;
+
+				if values.len() > *window {
+					*sum -= values.pop_front().unwrap_or_default();
+				}
+
+				*sum / values.len() as f64
+			},
+			Self::Exponential { alpha, output } => {
+				let filtered = output.map_or(value, |previous| *alpha * value + (1.0 - *alpha) * previous);
+				*output = Some(filtered);
+				filtered
+			},
+			Self::LowPass { time_constant, last } => {
+				let filtered = match *last {
+					// readings arriving out of order or with a repeated timestamp carry no new information
+					// about the signal's frequency content, so the previous output is held.
+					Some((previous_time, previous)) if timestamp <= previous_time => return previous,
+					Some((previous_time, previous)) => {
+						let dt = timestamp - previous_time;
+						let alpha = dt / (*time_constant + dt);
+						previous + alpha * (value - previous)
+					},
+					None => value,
+				};
+
+				*last = Some((timestamp, filtered));
+				filtered
+			},
+			Self::Median { window, values } => {
+				values.push_back(value);
+
+				if values.len() > *window {
+					values.pop_front();
+				}
+
+				let mut sorted = values.iter().copied().collect::<Vec<f64>>();
+				sorted.sort_unstable_by(f64::total_cmp);
+
+				let middle = sorted.len() / 2;
+
+				if sorted.len() % 2 == 0 {
+					(sorted[middle - 1] + sorted[middle]) / 2.0
+				} else {
+					sorted[middle]
+				}
+			},
+		}
+	}
+}
+
+/// A bank of stateful filters, one for each mapped sensor with a filter configured.
+///
+/// The bank should be rebuilt whenever new mappings are received, which resets the state of every filter.
+#[derive(Clone, Debug, Default)]
+pub struct FilterBank {
+	filters: HashMap<String, FilterState>,
+}
+
+impl FilterBank {
+	/// Constructs a new filter bank for the filters configured in the given mappings, rejecting any
+	/// filter whose parameters are out of range.
+	pub fn new(mappings: &[NodeMapping]) -> Result<Self, FilterError> {
+		let filters = mappings
+			.iter()
+			.filter_map(|mapping| {
+				let config = mapping.filter?;
+				Some(FilterState::new(&mapping.text_id, config).map(|filter| (mapping.text_id.clone(), filter)))
+			})
+			.collect::<Result<_, _>>()?;
+
+		Ok(FilterBank { filters })
+	}
+
+	/// Determines if the sensor with the given text ID has a filter configured.
+	pub fn is_filtered(&self, text_id: &str) -> bool {
+		self.filters.contains_key(text_id)
+	}
+
+	/// Passes a raw value through the filter of the given sensor, returning the filtered value.
+	///
+	/// The timestamp is the UNIX timestamp of the reading, in seconds, as in `DataPoint`. Values of
+	/// sensors without a filter, and values which are not finite, are returned unchanged, and the
+	/// latter are left out of the filter's state.
+	pub fn apply(&mut self, text_id: &str, value: f64, timestamp: f64) -> f64 {
+		match self.filters.get_mut(text_id) {
+			Some(filter) => filter.apply(value, timestamp),
+			None => value,
+		}
+	}
+
+	/// Filters a raw measurement and stores it in the vehicle state.
+	///
+	/// The filtered measurement goes into `sensor_readings` as usual, and if the sensor is filtered,
+	/// the raw measurement is also kept in `raw_sensor_readings`.
+	pub fn ingest(&mut self, state: &mut VehicleState, text_id: &str, measurement: Measurement, timestamp: f64) {
+		let Some(filter) = self.filters.get_mut(text_id) else {
+			state.sensor_readings.insert(text_id.to_owned(), measurement);
+			return;
+		};
+
+		let filtered = Measurement {
+			value: filter.apply(measurement.value, timestamp),
+			unit: measurement.unit,
+		};
+
+		state.sensor_readings.insert(text_id.to_owned(), filtered);
+		state.raw_sensor_readings.insert(text_id.to_owned(), measurement);
+	}
+}
@@ -0,0 +1,167 @@
+use crate::comm::{BoardHealth, BoardId, ChannelType, DataMessage, RailStatistics};
+use std::collections::{HashMap, VecDeque};
+
+/// The limits against which board health is judged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HealthLimits {
+	/// The lowest acceptable rail voltage, in volts.
+	pub min_rail_voltage: f64,
+
+	/// The highest acceptable rail voltage, in volts.
+	pub max_rail_voltage: f64,
+
+	/// The lowest acceptable rail current, in amps, below which the rail is considered open or dead.
+	pub min_rail_current: f64,
+
+	/// The highest acceptable rail current, in amps.
+	pub max_rail_current: f64,
+
+	/// The rail voltage, in volts, below which the board is considered to be browning out.
+	pub brownout_voltage: f64,
+
+	/// The time, in seconds, after the last message at which a board is considered offline.
+	pub timeout: f64,
+
+	/// The period, in seconds, over which the packet rate is averaged.
+	///
+	/// A window which is not positive disables the packet rate, which is then always reported as zero.
+	pub rate_window: f64,
+}
+
+#[derive(Clone, Debug)]
+struct BoardRecord {
+	rail_voltage: Option<RailStatistics>,
+	rail_current: Option<RailStatistics>,
+	brownout: bool,
+	brownout_count: u32,
+	arrivals: VecDeque<f64>,
+}
+
+impl BoardRecord {
+	fn update(statistics: &mut Option<RailStatistics>, value: f64) {
+		let updated = match *statistics {
+			Some(RailStatistics { min, max, .. }) => RailStatistics {
+				latest: value,
+				min: min.min(value),
+				max: max.max(value),
+			},
+			None => RailStatistics { latest: value, min: value, max: value },
+		};
+
+		*statistics = Some(updated);
+	}
+}
+
+/// Tracks the health of every data board heard from, using their rail voltage and current channels
+/// and the timing of their messages.
+#[derive(Clone, Debug)]
+pub struct BoardHealthMonitor {
+	limits: HealthLimits,
+	boards: HashMap<BoardId, BoardRecord>,
+}
+
+impl BoardHealthMonitor {
+	/// Constructs a new monitor which has not heard from any boards.
+	pub fn new(limits: HealthLimits) -> Self {
+		BoardHealthMonitor {
+			limits,
+			boards: HashMap::new(),
+		}
+	}
+
+	/// Records a message received at the given UNIX timestamp.
+	///
+	/// Messages which do not originate from a board, such as `FlightHeartbeat`, are ignored.
+	pub fn record(&mut self, message: &DataMessage, timestamp: f64) {
+		let (board_id, data_points) = match message {
+			DataMessage::Identity(board_id) | DataMessage::Bms(board_id) => (board_id, None),
+			DataMessage::Sam(board_id, data_points) => (board_id, Some(data_points)),
+			DataMessage::FlightHeartbeat => return,
+		};
+
+		let record = self.boards
+			.entry(board_id.clone())
+			.or_insert_with(|| BoardRecord {
+				rail_voltage: None,
+				rail_current: None,
+				brownout: false,
+				brownout_count: 0,
+				arrivals: VecDeque::new(),
+			});
+
+		record.arrivals.push_back(timestamp);
+
+		// the latest arrival is always kept, since it is also when the board was last seen
+		let rate_window = self.limits.rate_window.max(0.0);
+
+		while record.arrivals.front().is_some_and(|arrival| timestamp - arrival > rate_window) {
+			record.arrivals.pop_front();
+		}
+
+		for data_point in data_points.into_iter().flat_map(|data_points| data_points.iter()) {
+			match data_point.channel_type {
+				ChannelType::RailVoltage => {
+					BoardRecord::update(&mut record.rail_voltage, data_point.value);
+
+					let brownout = data_point.value < self.limits.brownout_voltage;
+
+					if brownout && !record.brownout {
+						record.brownout_count += 1;
+					}
+
+					record.brownout = brownout;
+				},
+				ChannelType::RailCurrent => BoardRecord::update(&mut record.rail_current, data_point.value),
+				_ => {},
+			}
+		}
+	}
+
+	/// Builds a health report of the given board as of the given UNIX timestamp.
+	///
+	/// Returns `None` if no message has been recorded from the board.
+	pub fn health(&self, board_id: &str, now: f64) -> Option<BoardHealth> {
+		let record = self.boards.get(board_id)?;
+		let last_seen = record.arrivals.back().copied().unwrap_or_default();
+
+		let voltage_in_range = record.rail_voltage.is_none_or(|voltage| {
+			(self.limits.min_rail_voltage..=self.limits.max_rail_voltage).contains(&voltage.latest)
+		});
+
+		let current_in_range = record.rail_current.is_none_or(|current| {
+			(self.limits.min_rail_current..=self.limits.max_rail_current).contains(&current.latest)
+		});
+
+		let packet_rate = if self.limits.rate_window > 0.0 {
+			record.arrivals
+				.iter()
+				.filter(|arrival| now - *arrival <= self.limits.rate_window)
+				.count() as f64 / self.limits.rate_window
+		} else {
+			0.0
+		};
+
+		Some(BoardHealth {
+			board_id: board_id.to_owned(),
+			rail_voltage: record.rail_voltage,
+			rail_current: record.rail_current,
+			rails_in_range: voltage_in_range && current_in_range,
+			brownout: record.brownout,
+			brownout_count: record.brownout_count,
+			last_seen,
+			online: now - last_seen <= self.limits.timeout,
+			packet_rate,
+		})
+	}
+
+	/// Builds health reports of every board heard from, sorted by board ID.
+	pub fn report(&self, now: f64) -> Vec<BoardHealth> {
+		let mut board_ids = self.boards.keys().collect::<Vec<_>>();
+		board_ids.sort();
+
+		board_ids
+			.into_iter()
+			.filter_map(|board_id| self.health(board_id, now))
+			.collect()
+	}
+}
@@ -7,6 +7,9 @@
 /// All structs and definitions related to communication between different subsystems.
 pub mod comm;
 
+/// Processing applied by the flight computer to incoming data before it enters the vehicle state.
+pub mod ingest;
+
 /// All components necessary to run Python sequences.
 #[cfg(feature = "sequences")]
 pub mod sequence;
@@ -0,0 +1,95 @@
+use std::sync::{atomic::Ordering, Mutex};
+
+use jeflog::fail;
+
+use crate::comm::Sequence;
+use super::{handle::{self, StopToken}, trigger, AbortStore, AbortStoreError, Namespace, SequenceHandle, SequenceRuntime};
+
+/// The sequence run upon an abort, received as a sequence named "abort".
+static ABORT_SEQUENCE: Mutex<Option<Sequence>> = Mutex::new(None);
+
+/// Where the abort sequence is persisted, if a store was given at initialization.
+static ABORT_STORE: Mutex<Option<AbortStore>> = Mutex::new(None);
+
+/// Stores the sequence to be run upon an abort, replacing any previously stored one.
+///
+/// If the library was initialized with an `AbortStore`, the sequence is also persisted to disk. The
+/// sequence is used for aborts even if persisting it fails, in which case the error is returned.
+pub fn set_abort_sequence(sequence: Sequence) -> Result<(), AbortStoreError> {
+	let store = ABORT_STORE
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+		.clone();
+
+	let result = store.map_or(Ok(()), |store| store.save(&sequence));
+	*ABORT_SEQUENCE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(sequence);
+	result
+}
+
+/// Uses the given store to persist the abort sequence, loading and verifying the one already saved in it.
+///
+/// If no valid abort sequence is saved, the error describing why is returned and any abort sequence
+/// already held in memory is kept, but the store is still used for subsequent abort sequences.
+pub(crate) fn load_abort_store(store: AbortStore) -> Result<(), AbortStoreError> {
+	let loaded = store.load();
+	*ABORT_STORE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(store);
+
+	*ABORT_SEQUENCE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(loaded?);
+	Ok(())
+}
+
+/// Gets the sequence which is run upon an abort, if one has been stored.
+pub fn abort_sequence() -> Option<Sequence> {
+	ABORT_SEQUENCE
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+		.clone()
+}
+
+/// Aborts every running sequence and trigger of the calling sequence's runtime, then runs the stored
+/// abort sequence in that runtime.
+///
+/// Every sequence running in the runtime is interrupted with an `AbortError`, every trigger of an
+/// engine using the runtime is deactivated, the runtime's device handler is told of the abort through
+/// `DeviceHandler::abort`, and the abort sequence is started on its own thread, where it cannot be
+/// stopped or aborted. Aborts requested while the abort sequence is running are ignored, so it runs
+/// exactly once. Returns a handle to the abort sequence, or `None` if an abort was already in progress
+/// or no abort sequence is stored.
+///
+/// Called from outside of a sequence, this aborts the default runtime. Sequences and triggers of
+/// other runtimes are never affected.
+pub fn run_abort() -> Option<SequenceHandle> {
+	match SequenceRuntime::current() {
+		Some(runtime) => abort_runtime(&runtime),
+		None => abort_runtime(SequenceRuntime::default_runtime()),
+	}
+}
+
+/// Aborts every running sequence and trigger of the given runtime, then runs the stored abort sequence in it.
+pub(crate) fn abort_runtime(runtime: &SequenceRuntime) -> Option<SequenceHandle> {
+	if runtime.aborting().swap(true, Ordering::SeqCst) {
+		return None;
+	}
+
+	runtime.abort_sequences();
+	trigger::deactivate_all(runtime);
+	runtime.notify_abort();
+
+	let Some(sequence) = abort_sequence() else {
+		fail!("No abort sequence is stored. Sequences and triggers were stopped, but nothing else was run.");
+		runtime.aborting().store(false, Ordering::SeqCst);
+		return None;
+	};
+
+	let finished = runtime.clone();
+
+	let handle = handle::spawn(runtime.clone(), sequence, Namespace::Isolated, StopToken::protected(), move |result| {
+		if let Err(error) = result {
+			fail!("Abort sequence failed: {error}");
+		}
+
+		finished.aborting().store(false, Ordering::SeqCst);
+	});
+
+	Some(handle)
+}
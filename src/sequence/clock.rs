@@ -0,0 +1,196 @@
+use std::{fmt, sync::{Arc, Condvar, Mutex, MutexGuard}, time::{Duration, Instant}};
+
+use super::{handle::StopToken, SequenceRuntime};
+
+/// How often a sleep on a simulated clock checks whether its sequence has been signalled, in real time.
+const INTERRUPT_POLL: Duration = Duration::from_millis(1);
+
+/// A source of time for sequences, used by `wait_for`, `wait_until`, `interval` and trigger cooldowns.
+///
+/// Each `SequenceRuntime` has its own clock. Flight uses the `RealClock`, while tests may substitute a `SimulatedClock` so that long sequences
+/// run without actually waiting.
+pub trait Clock: fmt::Debug + Send + Sync {
+	/// Gets the current time according to this clock.
+	fn now(&self) -> Instant;
+
+	/// Blocks the calling thread until this clock reaches the deadline, returning early if the
+	/// interrupt is set.
+	fn sleep_until(&self, deadline: Instant, interrupt: &Interrupt<'_>);
+}
+
+/// Allows a `Clock` to cut a sleep short when the sleeping sequence is stopped or aborted.
+#[derive(Debug)]
+pub struct Interrupt<'a> {
+	token: Option<&'a StopToken>,
+}
+
+impl<'a> Interrupt<'a> {
+	pub(crate) fn new(token: Option<&'a StopToken>) -> Self {
+		Interrupt { token }
+	}
+
+	/// Determines if the sleeping sequence has been signalled to stop or abort.
+	pub fn is_set(&self) -> bool {
+		self.token.is_some_and(StopToken::is_stopped)
+	}
+
+	/// Blocks for up to the given real duration, returning early if the interrupt is set.
+	///
+	/// Returns whether the interrupt is set.
+	pub fn wait(&self, timeout: Duration) -> bool {
+		match self.token {
+			Some(token) => token.sleep_until(Instant::now() + timeout).is_some(),
+			None => {
+				std::thread::sleep(timeout);
+				false
+			},
+		}
+	}
+}
+
+/// The clock of the real world, used in flight.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+
+	fn sleep_until(&self, deadline: Instant, interrupt: &Interrupt<'_>) {
+		interrupt.wait(deadline.saturating_duration_since(Instant::now()));
+	}
+}
+
+#[derive(Debug, Default)]
+struct SimulatedState {
+	/// How far the clock has advanced since it was constructed.
+	elapsed: Duration,
+
+	/// Incremented on every step, so that a sleeper can tell whether it has seen the latest one.
+	step: u64,
+
+	/// The number of sleepers which have seen the latest step and are still waiting.
+	sleeping: usize,
+}
+
+#[derive(Debug)]
+struct SimulatedTime {
+	origin: Instant,
+	state: Mutex<SimulatedState>,
+	advanced: Condvar,
+	auto_advance: bool,
+}
+
+/// A clock which only moves when told to, so that tests of long sequences run instantly and deterministically.
+///
+/// A clock constructed with `new` only advances when stepped with `advance`, while one constructed
+/// with `auto_advancing` jumps straight to the deadline of every sleep. Cloning a `SimulatedClock`
+/// yields another handle to the same clock.
+#[derive(Clone, Debug)]
+pub struct SimulatedClock {
+	inner: Arc<SimulatedTime>,
+}
+
+impl SimulatedClock {
+	fn with_auto_advance(auto_advance: bool) -> Self {
+		SimulatedClock {
+			inner: Arc::new(SimulatedTime {
+				origin: Instant::now(),
+				state: Mutex::new(SimulatedState::default()),
+				advanced: Condvar::new(),
+				auto_advance,
+			}),
+		}
+	}
+
+	/// Constructs a clock which only advances when stepped.
+	pub fn new() -> Self {
+		Self::with_auto_advance(false)
+	}
+
+	/// Constructs a clock which advances instantly to the deadline of every sleep.
+	pub fn auto_advancing() -> Self {
+		Self::with_auto_advance(true)
+	}
+
+	fn state(&self) -> MutexGuard<'_, SimulatedState> {
+		self.inner.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	/// Gets the total time this clock has advanced since it was constructed.
+	pub fn elapsed(&self) -> Duration {
+		self.state().elapsed
+	}
+
+	/// Steps this clock forward, waking every sleep whose deadline has been reached.
+	pub fn advance(&self, duration: Duration) {
+		let mut state = self.state();
+		state.elapsed += duration;
+		state.step += 1;
+
+		// every sleeper must see the step before it counts as sleeping again
+		state.sleeping = 0;
+		self.inner.advanced.notify_all();
+	}
+
+	/// Gets the number of threads sleeping on this clock which have seen its latest step, so that a
+	/// test can wait for sequences to reach a sleep, or to go back to sleep after a step that did not
+	/// reach their deadlines, before stepping the clock again.
+	pub fn sleeping(&self) -> usize {
+		self.state().sleeping
+	}
+}
+
+impl Default for SimulatedClock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Clock for SimulatedClock {
+	fn now(&self) -> Instant {
+		self.inner.origin + self.elapsed()
+	}
+
+	fn sleep_until(&self, deadline: Instant, interrupt: &Interrupt<'_>) {
+		let target = deadline.saturating_duration_since(self.inner.origin);
+		let mut state = self.state();
+
+		if self.inner.auto_advance {
+			if !interrupt.is_set() {
+				state.elapsed = state.elapsed.max(target);
+				self.inner.advanced.notify_all();
+			}
+
+			return;
+		}
+
+		// the interrupt is signalled through the stop token rather than this clock's condition
+		// variable, so it is checked periodically in real time while waiting to be stepped.
+		while state.elapsed < target && !interrupt.is_set() {
+			let step = state.step;
+			state.sleeping += 1;
+
+			state = self.inner.advanced
+				.wait_timeout(state, INTERRUPT_POLL)
+				.unwrap_or_else(|poisoned| poisoned.into_inner())
+				.0;
+
+			// a step resets the count of sleepers, so this one is only removed if it has not been
+			if state.step == step {
+				state.sleeping -= 1;
+			}
+		}
+	}
+}
+
+/// Gets the clock of the runtime running on the calling thread.
+pub(crate) fn clock() -> Arc<dyn Clock> {
+	SequenceRuntime::current().map_or_else(|| Arc::new(RealClock) as Arc<dyn Clock>, |runtime| runtime.clock())
+}
+
+/// Gets the current time according to the clock of the runtime running on the calling thread.
+pub(crate) fn now() -> Instant {
+	clock().now()
+}
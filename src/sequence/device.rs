@@ -1,7 +1,15 @@
-use crate::comm::ValveState;
-use jeflog::fail;
-use pyo3::{pyclass::CompareOp, pyclass, pymethods, types::{PyNone, PyString}, IntoPy, Py, PyAny, PyObject, PyResult, Python, ToPyObject};
-use super::{DeviceAction, DEVICE_HANDLER};
+use crate::comm::{CompositeValveState, Measurement, ValveState};
+use pyo3::{exceptions::PyValueError, pyclass::CompareOp, pyclass, pymethods, IntoPy, PyAny, PyObject, PyResult, Python};
+use super::{check_stopped, clock, handle, handler::with_device_handler, history::Window, unit::Duration, AbortError, DeviceError, SequenceRuntime};
+
+/// The window over which `Sensor.rate` is computed if no duration is given.
+const DEFAULT_RATE_WINDOW: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long a verified actuation waits for a valve to reach its commanded state if no timeout is given.
+const DEFAULT_ACTUATION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often a verified actuation checks the actual state of the valve.
+const VERIFY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
 
 /// A Python-exposed class that allows for interacting with a sensor.
 #[pyclass]
@@ -18,21 +26,103 @@ impl Sensor {
 		Sensor { name }
 	}
 
-	/// Reads the latest sensor measurements by indexing into the global vehicle state.
-	pub fn read(&self) -> PyObject {
-		let Some(device_handler) = &*DEVICE_HANDLER.lock().unwrap() else {
-			fail!("Device handler not set before accessing external device.");
-			return Python::with_gil(|py| PyNone::get(py).to_object(py));
-		};
+	/// Reads the latest sensor measurement from the device handler, recording it in the sensor's history.
+	pub fn read(&self) -> PyResult<PyObject> {
+		let measurement = self.measure()?;
+		Ok(Python::with_gil(|py| measurement.into_py(py)))
+	}
+
+	/// The average of the sensor over the last `duration`, weighting each reading by how long it held.
+	pub fn average(&self, duration: Duration) -> PyResult<PyObject> {
+		let window = self.window(duration.into())?;
+		statistic(window.average())
+	}
+
+	/// The lowest value of the sensor over the last `duration`.
+	pub fn min(&self, duration: Duration) -> PyResult<PyObject> {
+		let window = self.window(duration.into())?;
+		statistic(window.min())
+	}
+
+	/// The highest value of the sensor over the last `duration`.
+	pub fn max(&self, duration: Duration) -> PyResult<PyObject> {
+		let window = self.window(duration.into())?;
+		statistic(window.max())
+	}
+
+	/// The rate of change of the sensor per second over the last `duration`, or the last second by
+	/// default, such as a pressure of 5 psi for a pressurization rate of 5 psi/s.
+	///
+	/// Raises a `ValueError` if fewer than two readings were taken during the window.
+	#[pyo3(signature = (duration = None))]
+	pub fn rate(&self, duration: Option<Duration>) -> PyResult<PyObject> {
+		let window = self.window(duration.map_or(DEFAULT_RATE_WINDOW, Into::into))?;
+
+		window
+			.rate()
+			.map(|rate| Python::with_gil(|py| rate.into_py(py)))
+			.ok_or_else(|| PyValueError::new_err(format!("not enough readings of '{}' to compute a rate", self.name)))
+	}
+
+	/// Determines if the sensor has stayed above `value` for at least the last `duration`, which is
+	/// false if its history does not go back that far.
+	pub fn held_above(&self, value: &PyAny, duration: Duration) -> PyResult<bool> {
+		let window = self.window(duration.into())?;
+
+		if !window.is_covered() {
+			return Ok(false);
+		}
 
-		device_handler(&self.name, DeviceAction::ReadSensor)
+		for reading in window.values() {
+			if !reading.into_py(value.py()).as_ref(value.py()).gt(value)? {
+				return Ok(false);
+			}
+		}
+
+		Ok(true)
 	}
 
+	/// Compares the latest reading with another value, so that `sensor < x` means `sensor.read() < x`.
+	/// Comparisons with the sensor on the right, such as `x > sensor`, are reflected by Python onto this.
 	fn __richcmp__(&self, other: &PyAny, op: CompareOp) -> PyResult<bool> {
-		Ok(other.rich_compare(self.read(), op)?.is_true()?)
+		self.read()?
+			.into_ref(other.py())
+			.rich_compare(other, op)?
+			.is_true()
+	}
+}
+
+impl Sensor {
+	/// Reads the latest measurement from the device handler and records it in the sensor's history.
+	fn measure(&self) -> PyResult<Measurement> {
+		check_stopped()?;
+
+		let measurement = with_device_handler(&self.name, |handler| handler.read_sensor(&self.name))?;
+
+		if let Some(runtime) = SequenceRuntime::current() {
+			runtime.record_reading(&self.name, &measurement);
+		}
+
+		Ok(measurement)
+	}
+
+	/// Takes a fresh reading, then gets the history of the sensor over the last `duration`.
+	fn window(&self, duration: std::time::Duration) -> PyResult<Window> {
+		self.measure()?;
+
+		// reading the sensor would have failed if there were no runtime to read it through
+		let runtime = SequenceRuntime::current().ok_or(DeviceError::NoHandler)?;
+		Ok(runtime.history(&self.name, duration))
 	}
 }
 
+/// Converts a statistic of a sensor's history to a Python quantity.
+fn statistic(statistic: Option<Measurement>) -> PyResult<PyObject> {
+	// a fresh reading is always taken before computing a statistic, so the history is never empty
+	let statistic = statistic.ok_or_else(|| PyValueError::new_err("sensor has no readings"))?;
+	Ok(Python::with_gil(|py| statistic.into_py(py)))
+}
+
 /// A Python-exposed class that allows for interacting with a valve.
 #[pyclass]
 #[derive(Clone, Debug)]
@@ -48,54 +138,137 @@ impl Valve {
 		Valve { name }
 	}
 
-	/// Determines if the valve is open.
-	pub fn is_open(&self) -> Option<bool> {
-		let Some(device_handler) = &*DEVICE_HANDLER.lock().unwrap() else {
-			fail!("Device handler not set before accessing external device.");
-			return None;
-		};
+	/// The commanded and actual state of the valve.
+	#[getter]
+	pub fn state(&self) -> PyResult<CompositeValveState> {
+		check_stopped()?;
 
-		let state = device_handler(&self.name, DeviceAction::ReadValveState);
+		Ok(with_device_handler(&self.name, |handler| handler.read_valve_state(&self.name))?)
+	}
 
-		Python::with_gil(|py| {
-			let open: Py<PyAny> = "open".into_py(py);
-			state.into_ref(py).eq(open).ok()
-		})
+	/// The state which the valve was last commanded to.
+	#[getter]
+	pub fn commanded(&self) -> PyResult<ValveState> {
+		Ok(self.state()?.commanded)
 	}
 
-	/// Determines if the values is closed.
-	pub fn is_closed(&self) -> Option<bool> {
-		let Some(device_handler) = &*DEVICE_HANDLER.lock().unwrap() else {
-			fail!("Device handler not set before accessing external device.");
-			return None;
-		};
+	/// The actual state of the valve.
+	#[getter]
+	pub fn actual(&self) -> PyResult<ValveState> {
+		Ok(self.state()?.actual)
+	}
 
-		let state = device_handler(&self.name, DeviceAction::ReadValveState);
+	/// Determines if the valve is actually open.
+	pub fn is_open(&self) -> PyResult<bool> {
+		Ok(self.actual()? == ValveState::Open)
+	}
 
-		Python::with_gil(|py| {
-			let closed: Py<PyString> = "closed".into_py(py);
-			state.into_ref(py).eq(closed).ok()
-		})
+	/// Determines if the valve is actually closed.
+	pub fn is_closed(&self) -> PyResult<bool> {
+		Ok(self.actual()? == ValveState::Closed)
 	}
 
 	/// Instructs the SAM board to open the valve.
-	pub fn open(&self) {
-		self.actuate(true);
+	///
+	/// If `verify` is set, waits until the valve is actually open, raising an `ActuationTimeoutError`
+	/// if it is not open within the timeout, one second by default.
+	#[pyo3(signature = (verify = false, timeout = None))]
+	pub fn open(&self, verify: bool, timeout: Option<Duration>) -> PyResult<()> {
+		self.actuate(true, verify, timeout)
 	}
 
 	/// Instructs the SAM board to close the valve.
-	pub fn close(&self) {
-		self.actuate(false);
+	///
+	/// If `verify` is set, waits until the valve is actually closed, raising an `ActuationTimeoutError`
+	/// if it is not closed within the timeout, one second by default.
+	#[pyo3(signature = (verify = false, timeout = None))]
+	pub fn close(&self, verify: bool, timeout: Option<Duration>) -> PyResult<()> {
+		self.actuate(false, verify, timeout)
 	}
 
-	/// Instructs the SAM board to actuate a valve.
-	pub fn actuate(&self, open: bool) {
-		let Some(device_handler) = &*DEVICE_HANDLER.lock().unwrap() else {
-			fail!("Device handler not set before accessing external device.");
-			return;
-		};
+	/// Instructs the SAM board to actuate a valve, optionally verifying that it reaches the state.
+	#[pyo3(signature = (open, verify = false, timeout = None))]
+	pub fn actuate(&self, open: bool, verify: bool, timeout: Option<Duration>) -> PyResult<()> {
+		check_stopped()?;
 
 		let state = if open { ValveState::Open } else { ValveState::Closed };
-		device_handler(&self.name, DeviceAction::ActuateValve { state });
+		self.command(state)?;
+
+		if verify {
+			self.verify(state, timeout.map_or(DEFAULT_ACTUATION_TIMEOUT, Into::into))?;
+		}
+
+		Ok(())
+	}
+
+	/// Opens the valve, holds it open for `duration` and closes it again, verifying both actuations.
+	///
+	/// The duration is counted from when the valve is actually open. If the valve fails to actuate or
+	/// the sequence is stopped, the valve is still commanded closed before the exception propagates,
+	/// except upon an abort, which leaves the state of every valve to the abort sequence.
+	#[pyo3(signature = (duration, timeout = None))]
+	pub fn open_for(&self, py: Python<'_>, duration: Duration, timeout: Option<Duration>) -> PyResult<()> {
+		let timeout = timeout.map_or(DEFAULT_ACTUATION_TIMEOUT, Into::into);
+
+		let held = check_stopped()
+			.and_then(|_| self.command(ValveState::Open))
+			.and_then(|_| self.verify(ValveState::Open, timeout))
+			.and_then(|_| handle::sleep(duration.into()));
+
+		if let Err(error) = held {
+			if !error.is_instance_of::<AbortError>(py) {
+				// the valve is closed even though the sequence may have been stopped, so the stop
+				// check of actuate is bypassed
+				let _ = self.command(ValveState::Closed);
+			}
+
+			return Err(error);
+		}
+
+		self.command(ValveState::Closed)?;
+		self.verify(ValveState::Closed, timeout)
+	}
+
+	/// Opens the valve for `on` then closes it for `off`, `count` times, verifying every actuation.
+	///
+	/// The valve is left closed after the last pulse without waiting for another `off`.
+	#[pyo3(signature = (on, off, count, timeout = None))]
+	pub fn pulse(&self, py: Python<'_>, on: Duration, off: Duration, count: u32, timeout: Option<Duration>) -> PyResult<()> {
+		for pulse in 0..count {
+			if pulse > 0 {
+				handle::sleep(off.into())?;
+			}
+
+			self.open_for(py, on, timeout)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl Valve {
+	/// Commands the valve to a state through the device handler, without checking if the sequence
+	/// has been stopped.
+	fn command(&self, state: ValveState) -> PyResult<()> {
+		Ok(with_device_handler(&self.name, |handler| handler.actuate_valve(&self.name, state))?)
+	}
+
+	/// Waits until the valve actually reaches the given state, failing if it does not within the timeout.
+	fn verify(&self, state: ValveState, timeout: std::time::Duration) -> PyResult<()> {
+		let deadline = clock::now().checked_add(timeout);
+
+		while self.actual()? != state {
+			if deadline.is_some_and(|deadline| clock::now() >= deadline) {
+				return Err(DeviceError::ActuationTimeout {
+					valve: self.name.clone(),
+					state,
+					timeout,
+				}.into());
+			}
+
+			handle::sleep(VERIFY_INTERVAL)?;
+		}
+
+		Ok(())
 	}
 }
@@ -0,0 +1,64 @@
+use crate::comm::{PythonException, SequenceError};
+use pyo3::{types::PyTraceback, PyErr, PyResult, Python};
+use super::{AbortError, StopError};
+
+/// The file name Python gives to code run directly from a string, which is how sequence scripts are run.
+const SCRIPT_FILE_NAME: &str = "<string>";
+
+/// Extracts the type, message, traceback, and script line number from a Python exception.
+pub(crate) fn python_exception(py: Python<'_>, error: &PyErr) -> PythonException {
+	let exception_type = error
+		.get_type(py)
+		.name()
+		.map_or_else(|_| "Exception".to_owned(), ToOwned::to_owned);
+
+	let value = error.value(py);
+	let traceback = error.traceback(py);
+
+	// syntax errors are raised before the script has any frames, so the line is only available on the exception
+	let line = if error.is_instance_of::<pyo3::exceptions::PySyntaxError>(py) {
+		value.getattr("lineno").and_then(|line| line.extract()).ok()
+	} else {
+		traceback.and_then(|traceback| script_line(traceback).ok().flatten())
+	};
+
+	PythonException {
+		exception_type,
+		message: value.to_string(),
+		traceback: traceback.and_then(|traceback| traceback.format().ok()),
+		line,
+	}
+}
+
+/// Finds the line of the innermost traceback frame which belongs to the sequence script.
+fn script_line(traceback: &PyTraceback) -> PyResult<Option<u32>> {
+	let mut line = None;
+	let mut next = Some(traceback.as_ref());
+
+	while let Some(traceback) = next.filter(|traceback| !traceback.is_none()) {
+		let file_name = traceback
+			.getattr("tb_frame")?
+			.getattr("f_code")?
+			.getattr("co_filename")?
+			.extract::<&str>()?;
+
+		if file_name == SCRIPT_FILE_NAME {
+			line = Some(traceback.getattr("tb_lineno")?.extract()?);
+		}
+
+		next = Some(traceback.getattr("tb_next")?);
+	}
+
+	Ok(line)
+}
+
+/// Converts an exception which escaped a sequence script into the reason the sequence failed.
+pub(crate) fn script_error(py: Python<'_>, error: &PyErr) -> SequenceError {
+	if error.is_instance_of::<AbortError>(py) {
+		SequenceError::Aborted
+	} else if error.is_instance_of::<StopError>(py) {
+		SequenceError::Stopped
+	} else {
+		SequenceError::Exception(python_exception(py, error))
+	}
+}
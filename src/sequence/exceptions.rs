@@ -0,0 +1,63 @@
+//! Python exceptions raised inside a sequence when a device cannot be accessed, or when a sandboxed
+//! sequence attempts something outside of its `Sandbox`.
+//!
+//! Each device exception corresponds to a variant of the Rust `DeviceError`, and all derive from the
+//! Python `DeviceError` so that a sequence may handle every device failure at once.
+
+use pyo3::{create_exception, exceptions::PyException};
+
+create_exception!(
+	sequences,
+	DeviceError,
+	PyException,
+	"Raised when a device cannot be accessed, and the base of every more specific device exception."
+);
+
+create_exception!(
+	sequences,
+	UnknownDeviceError,
+	DeviceError,
+	"Raised when accessing a device which does not exist."
+);
+
+create_exception!(
+	sequences,
+	DeviceOfflineError,
+	DeviceError,
+	"Raised when accessing a device whose board is not responding."
+);
+
+create_exception!(
+	sequences,
+	DeviceUnavailableError,
+	DeviceError,
+	"Raised when reading a device which has not reported a value yet."
+);
+
+create_exception!(
+	sequences,
+	DeviceRemovedError,
+	UnknownDeviceError,
+	"Raised when accessing a device whose mapping was removed while the sequence was running."
+);
+
+create_exception!(
+	sequences,
+	ActuationRejectedError,
+	DeviceError,
+	"Raised when a board refuses to actuate a valve."
+);
+
+create_exception!(
+	sequences,
+	ActuationTimeoutError,
+	DeviceError,
+	"Raised when a valve does not actually reach its commanded state before a verified actuation times out."
+);
+
+create_exception!(
+	sequences,
+	SandboxError,
+	PyException,
+	"Raised when a sandboxed sequence uses a builtin, module or attribute which its sandbox does not allow."
+);
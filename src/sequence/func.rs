@@ -1,17 +1,22 @@
-use std::{thread, time::Instant};
+use std::time::Instant;
 
-use jeflog::fail;
-use pyo3::{pyclass, pyfunction, pymethods, PyAny, PyRef, PyRefMut, PyResult};
+use pyo3::{pyclass, pyfunction, pymethods, PyAny, PyRef, PyRefMut, PyResult, Python};
 
 use crate::sequence::unit::Duration;
 
-use super::{DeviceAction, DEVICE_HANDLER};
+use crate::comm::LogLevel;
+use super::{check_stopped, clock, handle, output, AbortError};
 
 /// A Python-exposed function which waits the thread for the given duration.
 #[pyfunction]
-pub fn wait_for(duration: Duration) {
-	// TODO: considering using a different way to sleep, possibly sleeping only the GIL?
-	thread::sleep(duration.into());
+pub fn wait_for(duration: Duration) -> PyResult<()> {
+	handle::sleep(duration.into())
+}
+
+/// A Python-exposed function which logs a message at the given level, attributed to the running sequence.
+#[pyfunction]
+pub fn log(level: LogLevel, message: &str) {
+	output::log(level, message);
 }
 
 /// A Python-exposed function which waits until a condition function is true, given an optional timeout and interval between checking.
@@ -20,24 +25,33 @@ pub fn wait_until(condition: &PyAny, timeout: Option<Duration>, poll_interval: O
 	let timeout = timeout.map_or(std::time::Duration::MAX, Into::into);
 	let interval = poll_interval.map_or(std::time::Duration::from_millis(10), Into::into);
 
-	let end_time = Instant::now() + timeout;
+	let end_time = clock::now().checked_add(timeout);
 
-	while !condition.call0()?.is_true()? && Instant::now() < end_time {
-		thread::sleep(interval);
+	check_stopped()?;
+
+	while !condition.call0()?.is_true()? && end_time.is_none_or(|end_time| clock::now() < end_time) {
+		handle::sleep(interval)?;
 	}
 
 	Ok(())
 }
 
-/// A Python-exposed function which immediately runs the abort sequence.
+/// A Python-exposed function which immediately aborts every sequence and trigger of the calling
+/// sequence's runtime and runs the abort sequence.
+///
+/// The calling sequence is interrupted with an `AbortError` like every other, unless it is the abort
+/// sequence itself, in which case the call has no effect.
 #[pyfunction]
-pub fn abort() {
-	let Some(device_handler) = &*DEVICE_HANDLER.lock().unwrap() else {
-		fail!("Device handler not set before accessing external device.");
-		return;
-	};
-
-	device_handler("", DeviceAction::Abort);
+pub fn abort(py: Python<'_>) -> PyResult<()> {
+	// the GIL is released while aborting because the trigger engines must be locked, and an engine
+	// may be holding its lock while waiting on the GIL to evaluate a condition.
+	py.allow_threads(super::run_abort);
+
+	if handle::current_token().is_some_and(|token| token.is_protected()) {
+		Ok(())
+	} else {
+		Err(AbortError::new_err("sequence was aborted"))
+	}
 }
 
 /// Iterator which only yields the iteration after waiting for the given period.
@@ -56,13 +70,12 @@ impl IntervalIterator {
 		_self
 	}
 
-	fn __next__(mut _self: PyRefMut<'_, Self>) -> Option<i64> {
+	fn __next__(mut _self: PyRefMut<'_, Self>) -> PyResult<Option<i64>> {
 		if _self.iteration >= _self.total {
-			return None;
+			return Ok(None);
 		}
 
-		let wait = _self.next_tick - Instant::now();
-		thread::sleep(wait);
+		handle::sleep_until(_self.next_tick)?;
 
 		let iteration = _self.iteration;
 		let next_tick = _self.next_tick + _self.period;
@@ -70,7 +83,7 @@ impl IntervalIterator {
 		_self.next_tick = next_tick;
 		_self.iteration += 1;
 
-		Some(iteration)
+		Ok(Some(iteration))
 	}
 }
 
@@ -78,7 +91,7 @@ impl IntervalIterator {
 #[pyfunction]
 pub fn interval(count: i64, period: Duration) -> IntervalIterator {
 	IntervalIterator {
-		next_tick: Instant::now(),
+		next_tick: clock::now(),
 		period: period.into(),
 		iteration: 0,
 		total: count
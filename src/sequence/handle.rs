@@ -0,0 +1,278 @@
+use std::{cell::RefCell, sync::{Arc, Condvar, Mutex, Weak}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+
+use pyo3::{PyErr, PyResult, Python};
+
+use crate::comm::{Sequence, SequenceError};
+use super::{clock::{self, Interrupt}, watchdog, AbortError, Namespace, SequenceRuntime, StopError};
+
+thread_local! {
+	/// The stop token of the sequence running on the current thread, if any.
+	static CURRENT_TOKEN: RefCell<Option<StopToken>> = const { RefCell::new(None) };
+}
+
+/// The reason a sequence has been signalled to end early.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Signal {
+	/// The sequence was stopped individually, raising a `StopError`.
+	Stop,
+
+	/// Every sequence in the runtime is being aborted, raising an `AbortError`.
+	Abort,
+}
+
+impl Signal {
+	fn into_err(self) -> PyErr {
+		match self {
+			Signal::Stop => StopError::new_err("sequence was stopped"),
+			Signal::Abort => AbortError::new_err("sequence was aborted"),
+		}
+	}
+}
+
+#[derive(Debug, Default)]
+struct TokenState {
+	signal: Mutex<Option<Signal>>,
+	condvar: Condvar,
+	protected: bool,
+}
+
+/// A flag shared between a running sequence and its handles which signals that the sequence should stop.
+///
+/// A condition variable is paired with the flag so that sequences sleeping inside `wait_for` and
+/// friends are woken immediately instead of finishing their sleep.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StopToken {
+	inner: Arc<TokenState>,
+}
+
+impl StopToken {
+	/// Constructs a token which ignores every signal, used to protect the abort sequence from interruption.
+	pub fn protected() -> Self {
+		StopToken {
+			inner: Arc::new(TokenState {
+				protected: true,
+				..TokenState::default()
+			}),
+		}
+	}
+
+	fn signal(&self, signal: Signal) {
+		if self.inner.protected {
+			return;
+		}
+
+		let mut current = self.inner.signal.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+		// an abort takes precedence over a stop which has not been acted upon yet
+		if *current != Some(Signal::Abort) {
+			*current = Some(signal);
+		}
+
+		self.inner.condvar.notify_all();
+	}
+
+	/// Signals the sequence to stop and wakes it if it is sleeping.
+	pub fn stop(&self) {
+		self.signal(Signal::Stop);
+	}
+
+	/// Signals the sequence to abort and wakes it if it is sleeping.
+	pub fn abort(&self) {
+		self.signal(Signal::Abort);
+	}
+
+	/// Gets the signal which the sequence has received, if any.
+	pub fn received(&self) -> Option<Signal> {
+		*self.inner.signal.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	/// Determines if the sequence has been signalled to stop or abort.
+	pub fn is_stopped(&self) -> bool {
+		self.received().is_some()
+	}
+
+	/// Determines if this token ignores signals.
+	pub fn is_protected(&self) -> bool {
+		self.inner.protected
+	}
+
+	/// Determines if two tokens belong to the same sequence run.
+	pub fn ptr_eq(&self, other: &StopToken) -> bool {
+		Arc::ptr_eq(&self.inner, &other.inner)
+	}
+
+	/// Sleeps until the deadline or until the sequence is signalled, whichever comes first.
+	///
+	/// Returns the signal which cut the sleep short, if any.
+	pub fn sleep_until(&self, deadline: Instant) -> Option<Signal> {
+		let mut signal = self.inner.signal.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+		while signal.is_none() {
+			let now = Instant::now();
+
+			if now >= deadline {
+				break;
+			}
+
+			signal = self.inner.condvar
+				.wait_timeout(signal, deadline - now)
+				.unwrap_or_else(|poisoned| poisoned.into_inner())
+				.0;
+		}
+
+		*signal
+	}
+}
+
+/// The tokens of every sequence alive in a runtime, so that an abort of that runtime can reach all of them.
+#[derive(Debug, Default)]
+pub(crate) struct LiveTokens {
+	tokens: Mutex<Vec<Weak<TokenState>>>,
+}
+
+impl LiveTokens {
+	/// Constructs a token for a sequence, registering it to be signalled by `abort_all`.
+	pub fn token(&self) -> StopToken {
+		let token = StopToken::default();
+
+		let mut tokens = self.tokens.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+		tokens.retain(|token| token.strong_count() > 0);
+		tokens.push(Arc::downgrade(&token.inner));
+
+		token
+	}
+
+	/// Signals every live sequence registered here to abort.
+	pub fn abort_all(&self) {
+		let tokens = self.tokens.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+		for inner in tokens.iter().filter_map(Weak::upgrade) {
+			StopToken { inner }.abort();
+		}
+	}
+}
+
+/// Gets the stop token of the sequence running on the calling thread.
+pub(crate) fn current_token() -> Option<StopToken> {
+	CURRENT_TOKEN.with_borrow(Clone::clone)
+}
+
+/// Runs the closure with the given token as the stop token of the calling thread, restoring the
+/// previous token afterwards.
+pub(crate) fn with_token<T>(token: StopToken, f: impl FnOnce() -> T) -> T {
+	let previous = CURRENT_TOKEN.replace(Some(token));
+	let result = f();
+	CURRENT_TOKEN.set(previous);
+	result
+}
+
+/// Raises a `StopError` or `AbortError` in the calling sequence if it has been signalled.
+///
+/// This is called by every Python-exposed function which waits or touches a device, which are the
+/// points at which a sequence may be interrupted.
+pub(crate) fn check_stopped() -> PyResult<()> {
+	match current_token().and_then(|token| token.received()) {
+		Some(signal) => Err(signal.into_err()),
+		None => Ok(()),
+	}
+}
+
+/// Sleeps the calling sequence until the sequence clock reaches the deadline, raising a `StopError`
+/// or `AbortError` if it is signalled in the meantime.
+///
+/// The GIL is released for the duration of the sleep so that other sequences may run in the meantime.
+pub(crate) fn sleep_until(deadline: Instant) -> PyResult<()> {
+	let token = current_token();
+	let clock = clock::clock();
+
+	Python::with_gil(|py| {
+		watchdog::pause();
+		py.allow_threads(|| clock.sleep_until(deadline, &Interrupt::new(token.as_ref())));
+		watchdog::resume();
+	});
+
+	match token.and_then(|token| token.received()) {
+		Some(signal) => Err(signal.into_err()),
+		None => Ok(()),
+	}
+}
+
+/// Sleeps the calling sequence for the given duration of the sequence clock, raising a `StopError`
+/// or `AbortError` if it is signalled in the meantime.
+pub(crate) fn sleep(duration: Duration) -> PyResult<()> {
+	let now = clock::now();
+
+	// durations too large to represent as a deadline, such as Duration::MAX, are effectively forever
+	let deadline = now
+		.checked_add(duration)
+		.unwrap_or_else(|| now + Duration::from_secs(u32::MAX as u64));
+
+	sleep_until(deadline)
+}
+
+/// A handle to a sequence running on its own thread, which may be used to stop it from any thread.
+#[derive(Debug)]
+pub struct SequenceHandle {
+	name: String,
+	token: StopToken,
+	thread: JoinHandle<Result<(), SequenceError>>,
+}
+
+impl SequenceHandle {
+	/// The name of the sequence which this handle controls.
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Signals the sequence to stop.
+	///
+	/// The sequence raises a `StopError` the next time it waits or accesses a device, and waits
+	/// already in progress are interrupted immediately. The abort sequence cannot be stopped.
+	pub fn stop(&self) {
+		self.token.stop();
+	}
+
+	/// Determines if the sequence has finished running, whether successfully or not.
+	pub fn is_finished(&self) -> bool {
+		self.thread.is_finished()
+	}
+
+	/// Waits for the sequence to finish, returning the result of running it.
+	pub fn join(self) -> Result<(), SequenceError> {
+		self.thread
+			.join()
+			.unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+	}
+}
+
+/// Runs a sequence on a new thread in the given runtime and namespace with the given stop token, calling `on_finish` with the result
+/// on that thread before it exits.
+pub(crate) fn spawn(
+	runtime: SequenceRuntime,
+	sequence: Sequence,
+	namespace: Namespace,
+	token: StopToken,
+	on_finish: impl FnOnce(&Result<(), SequenceError>) + Send + 'static,
+) -> SequenceHandle {
+	let name = sequence.name.clone();
+
+	let thread = {
+		let token = token.clone();
+
+		thread::spawn(move || {
+			let result = with_token(token, || runtime.run_in(sequence, namespace));
+			on_finish(&result);
+			result
+		})
+	};
+
+	SequenceHandle { name, token, thread }
+}
+
+/// Starts running a sequence on a new thread using the default runtime, returning a handle which can
+/// be used to stop it.
+///
+/// The `initialize` function must be called before this.
+pub fn start(sequence: Sequence) -> SequenceHandle {
+	SequenceRuntime::default_runtime().start(sequence)
+}
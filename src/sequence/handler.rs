@@ -0,0 +1,116 @@
+use std::{error::Error, fmt, time::Duration};
+
+use jeflog::fail;
+use pyo3::PyErr;
+
+use crate::comm::{CompositeValveState, Measurement, ValveState};
+use super::{exceptions, SequenceRuntime};
+
+/// Interacts with external boards on behalf of sequences, such as by reading sensors and actuating valves.
+///
+/// Each method is given the text ID of the target device. Errors are raised inside the calling
+/// sequence as the Python exception corresponding to the `DeviceError`.
+pub trait DeviceHandler: Send {
+	/// Reads the latest measurement of a sensor.
+	fn read_sensor(&self, name: &str) -> Result<Measurement, DeviceError>;
+
+	/// Reads the commanded and actual estimated state of a valve.
+	fn read_valve_state(&self, name: &str) -> Result<CompositeValveState, DeviceError>;
+
+	/// Commands a valve to actuate to match the given state, either `Open` or `Closed`.
+	fn actuate_valve(&self, name: &str, state: ValveState) -> Result<(), DeviceError>;
+
+	/// Notifies the handler that the runtime is being aborted, after its sequences have been
+	/// interrupted and its triggers deactivated but before the abort sequence is started.
+	///
+	/// This is called without the GIL held, whether the abort was requested by a sequence, by the
+	/// watchdog or by `run_abort`. Does nothing by default.
+	fn abort(&self) {}
+}
+
+/// The reason a device could not be accessed by a sequence.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeviceError {
+	/// No device handler has been set.
+	NoHandler,
+
+	/// No device with the contained name exists, raised as an `UnknownDeviceError`.
+	UnknownDevice(String),
+
+	/// The board of the device with the contained name is not responding, raised as a `DeviceOfflineError`.
+	Offline(String),
+
+	/// The device with the contained name exists but has not reported a value yet, raised as a `DeviceUnavailableError`.
+	Unavailable(String),
+
+	/// The device with the contained name was removed from the mappings while the sequence was
+	/// running, raised as a `DeviceRemovedError`.
+	Removed(String),
+
+	/// The board refused to actuate a valve, raised as an `ActuationRejectedError`.
+	ActuationRejected {
+		/// The name of the valve which was to be actuated.
+		valve: String,
+
+		/// Why the actuation was rejected.
+		reason: String,
+	},
+
+	/// A valve was commanded but did not actually reach the commanded state in time, raised as an
+	/// `ActuationTimeoutError`.
+	ActuationTimeout {
+		/// The name of the valve which was actuated.
+		valve: String,
+
+		/// The state the valve was commanded to.
+		state: ValveState,
+
+		/// How long the valve was given to reach the state.
+		timeout: Duration,
+	},
+}
+
+impl fmt::Display for DeviceError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::NoHandler => write!(f, "no device handler has been set"),
+			Self::UnknownDevice(name) => write!(f, "unknown device '{name}'"),
+			Self::Offline(name) => write!(f, "the board of device '{name}' is offline"),
+			Self::Unavailable(name) => write!(f, "device '{name}' has not reported a value yet"),
+			Self::Removed(name) => write!(f, "device '{name}' was removed from the mappings"),
+			Self::ActuationRejected { valve, reason } => write!(f, "actuation of valve '{valve}' was rejected: {reason}"),
+			Self::ActuationTimeout { valve, state, timeout } => write!(f, "valve '{valve}' did not become {state} within {timeout:?}"),
+		}
+	}
+}
+
+impl Error for DeviceError {}
+
+impl From<DeviceError> for PyErr {
+	fn from(error: DeviceError) -> Self {
+		let message = error.to_string();
+
+		match error {
+			DeviceError::NoHandler => exceptions::DeviceError::new_err(message),
+			DeviceError::UnknownDevice(_) => exceptions::UnknownDeviceError::new_err(message),
+			DeviceError::Offline(_) => exceptions::DeviceOfflineError::new_err(message),
+			DeviceError::Unavailable(_) => exceptions::DeviceUnavailableError::new_err(message),
+			DeviceError::Removed(_) => exceptions::DeviceRemovedError::new_err(message),
+			DeviceError::ActuationRejected { .. } => exceptions::ActuationRejectedError::new_err(message),
+			DeviceError::ActuationTimeout { .. } => exceptions::ActuationTimeoutError::new_err(message),
+		}
+	}
+}
+
+/// Runs the closure with the device handler of the runtime running on the calling thread, failing
+/// if none has been set or if the named device has been removed from the mappings.
+pub(crate) fn with_device_handler<T>(name: &str, f: impl FnOnce(&dyn DeviceHandler) -> Result<T, DeviceError>) -> Result<T, DeviceError> {
+	match SequenceRuntime::current() {
+		Some(runtime) if runtime.is_removed(name) => Err(DeviceError::Removed(name.to_owned())),
+		Some(runtime) => runtime.with_device_handler(f),
+		None => {
+			fail!("Device handler not set before accessing external device.");
+			Err(DeviceError::NoHandler)
+		},
+	}
+}
@@ -0,0 +1,172 @@
+use std::{collections::{HashMap, VecDeque}, time::{Duration, Instant}};
+
+use crate::comm::{Measurement, Unit};
+
+/// The most readings kept for any one sensor.
+const HISTORY_CAPACITY: usize = 4096;
+
+/// How long readings are kept for, after which they are discarded.
+const HISTORY_RETENTION: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Sample {
+	time: Instant,
+	value: f64,
+	unit: Unit,
+}
+
+/// The recent readings of every sensor, bounded in both count and age, from which sequences compute
+/// statistics such as averages and rates.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+	sensors: HashMap<String, VecDeque<Sample>>,
+}
+
+impl History {
+	/// Records a reading of a sensor taken at the given time, discarding readings which have expired.
+	pub(crate) fn record(&mut self, name: &str, time: Instant, measurement: &Measurement) {
+		let samples = self.sensors.entry(name.to_owned()).or_default();
+
+		// readings from several threads may arrive slightly out of order, so they are inserted in place
+		let position = samples.partition_point(|sample| sample.time <= time);
+
+		samples.insert(position, Sample {
+			time,
+			value: measurement.value,
+			unit: measurement.unit,
+		});
+
+		let newest = samples.back().map_or(time, |sample| sample.time);
+
+		while samples.len() > HISTORY_CAPACITY || samples.front().is_some_and(|sample| newest.saturating_duration_since(sample.time) > HISTORY_RETENTION) {
+			samples.pop_front();
+		}
+	}
+
+	/// Gets the readings of a sensor which were in effect from `start` until `end`, treating each
+	/// reading as held until the next one.
+	///
+	/// If the unit of the sensor changed during the window, such as after its mapping was replaced,
+	/// the window instead starts at the first reading in the latest unit, and is not covered.
+	pub(crate) fn window(&self, name: &str, start: Instant, end: Instant) -> Window {
+		let Some(samples) = self.sensors.get(name) else {
+			return Window { start, end, covered: false, samples: Vec::new() };
+		};
+
+		let first = samples.partition_point(|sample| sample.time <= start);
+		let last = samples.partition_point(|sample| sample.time <= end);
+
+		// the reading taken at or before the start of the window is the one held at its start
+		let held = first.saturating_sub(1);
+		let mut samples = samples.range(held..last).copied().collect::<Vec<_>>();
+
+		// readings in different units cannot be combined, so only those in the latest unit are kept
+		let unit = samples.last().map(|sample| sample.unit);
+		let changed = samples.iter().rposition(|sample| Some(sample.unit) != unit);
+
+		if let Some(changed) = changed {
+			samples.drain(..=changed);
+
+			return Window {
+				start: samples[0].time.max(start),
+				end,
+				covered: false,
+				samples,
+			};
+		}
+
+		Window {
+			start,
+			end,
+			covered: first > 0,
+			samples,
+		}
+	}
+}
+
+/// The readings of a sensor over a period of time, in the order they were taken, which all share a unit.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Window {
+	start: Instant,
+	end: Instant,
+	covered: bool,
+	samples: Vec<Sample>,
+}
+
+impl Window {
+	/// Determines if a reading was held at the start of the window, so that the window reflects the
+	/// entire period rather than only its end.
+	pub(crate) fn is_covered(&self) -> bool {
+		self.covered
+	}
+
+	/// The values held during the window, each with its unit.
+	pub(crate) fn values(&self) -> impl Iterator<Item = Measurement> + '_ {
+		self.samples
+			.iter()
+			.map(|sample| Measurement { value: sample.value, unit: sample.unit })
+	}
+
+	fn unit(&self) -> Option<Unit> {
+		self.samples.last().map(|sample| sample.unit)
+	}
+
+	/// The lowest value held during the window.
+	pub(crate) fn min(&self) -> Option<Measurement> {
+		let value = self.samples.iter().map(|sample| sample.value).min_by(f64::total_cmp)?;
+		Some(Measurement { value, unit: self.unit()? })
+	}
+
+	/// The highest value held during the window.
+	pub(crate) fn max(&self) -> Option<Measurement> {
+		let value = self.samples.iter().map(|sample| sample.value).max_by(f64::total_cmp)?;
+		Some(Measurement { value, unit: self.unit()? })
+	}
+
+	/// The average value held during the window, weighting each reading by how long it was held.
+	///
+	/// If every reading was taken at the end of the window, the latest reading is the average.
+	pub(crate) fn average(&self) -> Option<Measurement> {
+		let unit = self.unit()?;
+		let mut total = 0.0;
+		let mut weight = 0.0;
+
+		for (index, sample) in self.samples.iter().enumerate() {
+			let from = sample.time.max(self.start);
+			let until = self.samples.get(index + 1).map_or(self.end, |next| next.time);
+			let held = until.saturating_duration_since(from).as_secs_f64();
+
+			total += sample.value * held;
+			weight += held;
+		}
+
+		let value = if weight > 0.0 {
+			total / weight
+		} else {
+			self.samples.last()?.value
+		};
+
+		Some(Measurement { value, unit })
+	}
+
+	/// The rate of change over the window per second, fitted by least squares to the readings taken
+	/// during the window, or `None` if fewer than two readings were taken at different times.
+	pub(crate) fn rate(&self) -> Option<Measurement> {
+		let unit = self.unit()?;
+
+		let points = self.samples
+			.iter()
+			.filter(|sample| sample.time >= self.start)
+			.map(|sample| (sample.time.saturating_duration_since(self.start).as_secs_f64(), sample.value))
+			.collect::<Vec<_>>();
+
+		let count = points.len() as f64;
+		let mean_time = points.iter().map(|(time, _)| time).sum::<f64>() / count;
+		let mean_value = points.iter().map(|(_, value)| value).sum::<f64>() / count;
+
+		let covariance = points.iter().map(|(time, value)| (time - mean_time) * (value - mean_value)).sum::<f64>();
+		let variance = points.iter().map(|(time, _)| (time - mean_time).powi(2)).sum::<f64>();
+
+		(variance > 0.0).then(|| Measurement { value: covariance / variance, unit })
+	}
+}
@@ -0,0 +1,164 @@
+use std::{collections::HashMap, sync::{Arc, Condvar, Mutex, MutexGuard}};
+
+use crate::comm::{Sequence, SequenceError, SequenceStatus};
+use super::{handle::{self, StopToken}, Namespace, SequenceRuntime};
+
+#[derive(Debug)]
+struct Entry {
+	status: SequenceStatus,
+	token: StopToken,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+	entries: Mutex<HashMap<String, Entry>>,
+	finished: Condvar,
+}
+
+impl Registry {
+	fn lock(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+		self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+}
+
+/// Runs sequences concurrently, each on its own thread, and keeps a registry of them by name.
+///
+/// Cloning a `SequenceManager` yields another handle to the same registry.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceManager {
+	registry: Arc<Registry>,
+
+	/// The runtime in which sequences are run, or `None` to use the default runtime.
+	runtime: Option<SequenceRuntime>,
+}
+
+impl SequenceManager {
+	/// Constructs a new manager with no sequences which runs them in the default runtime.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Constructs a new manager with no sequences which runs them in the given runtime.
+	pub fn with_runtime(runtime: SequenceRuntime) -> Self {
+		SequenceManager {
+			registry: Arc::default(),
+			runtime: Some(runtime),
+		}
+	}
+
+	/// The runtime in which this manager runs sequences.
+	pub fn runtime(&self) -> SequenceRuntime {
+		self.runtime
+			.clone()
+			.unwrap_or_else(|| SequenceRuntime::default_runtime().clone())
+	}
+
+	/// Starts running a sequence on its own thread.
+	///
+	/// Fails with `SequenceError::AlreadyRunning` if a sequence with the same name is still running.
+	/// A sequence which has finished may be started again under the same name, which replaces its status.
+	pub fn start(&self, sequence: Sequence) -> Result<(), SequenceError> {
+		let mut entries = self.registry.lock();
+
+		if entries.get(&sequence.name).is_some_and(|entry| entry.status == SequenceStatus::Running) {
+			return Err(SequenceError::AlreadyRunning(sequence.name));
+		}
+
+		let runtime = self.runtime();
+		let token = runtime.token();
+		let name = sequence.name.clone();
+
+		entries.insert(name.clone(), Entry {
+			status: SequenceStatus::Running,
+			token: token.clone(),
+		});
+
+		// the registry lock is held until after spawning so that the thread cannot report
+		// its result before the entry it belongs to exists.
+		let registry = self.registry.clone();
+
+		handle::spawn(runtime, sequence, Namespace::Isolated, token.clone(), move |result| {
+			let mut entries = registry.lock();
+
+			// the entry may have been replaced if the sequence was restarted after being stopped,
+			// so only the entry belonging to this run is updated.
+			if let Some(entry) = entries.get_mut(&name).filter(|entry| entry.token.ptr_eq(&token)) {
+				entry.status = result.clone().into();
+			}
+
+			registry.finished.notify_all();
+		});
+
+		Ok(())
+	}
+
+	/// Signals the running sequence with the given name to stop.
+	///
+	/// Returns `false` if no sequence with that name is running.
+	pub fn stop(&self, name: &str) -> bool {
+		let entries = self.registry.lock();
+
+		match entries.get(name) {
+			Some(entry) if entry.status == SequenceStatus::Running => {
+				entry.token.stop();
+				true
+			},
+			_ => false,
+		}
+	}
+
+	/// Signals every running sequence to stop.
+	pub fn stop_all(&self) {
+		for entry in self.registry.lock().values() {
+			if entry.status == SequenceStatus::Running {
+				entry.token.stop();
+			}
+		}
+	}
+
+	/// Gets the status of the sequence with the given name, if it has ever been started.
+	pub fn status(&self, name: &str) -> Option<SequenceStatus> {
+		self.registry
+			.lock()
+			.get(name)
+			.map(|entry| entry.status.clone())
+	}
+
+	/// Gets the status of every sequence which has been started, by name.
+	pub fn statuses(&self) -> HashMap<String, SequenceStatus> {
+		self.registry
+			.lock()
+			.iter()
+			.map(|(name, entry)| (name.clone(), entry.status.clone()))
+			.collect()
+	}
+
+	/// Gets the names of all sequences which are currently running.
+	pub fn running(&self) -> Vec<String> {
+		self.registry
+			.lock()
+			.iter()
+			.filter(|(_, entry)| entry.status == SequenceStatus::Running)
+			.map(|(name, _)| name.clone())
+			.collect()
+	}
+
+	/// Blocks until the sequence with the given name is no longer running, returning its final status.
+	///
+	/// Returns `None` if no sequence with that name has been started.
+	pub fn wait(&self, name: &str) -> Option<SequenceStatus> {
+		let mut entries = self.registry.lock();
+
+		loop {
+			match entries.get(name) {
+				Some(entry) if entry.status == SequenceStatus::Running => {},
+				Some(entry) => return Some(entry.status.clone()),
+				None => return None,
+			}
+
+			entries = self.registry.finished
+				.wait(entries)
+				.unwrap_or_else(|poisoned| poisoned.into_inner());
+		}
+	}
+}
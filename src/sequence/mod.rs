@@ -1,15 +1,45 @@
+mod aborting;
+mod clock;
 mod device;
+mod error;
+pub mod exceptions;
 mod func;
+mod handle;
+mod handler;
+mod history;
+mod manager;
+mod output;
+mod parameter;
+mod runtime;
+mod sandbox;
+mod simulation;
+mod store;
+mod trigger;
 mod unit;
+mod validate;
+mod watchdog;
 
+pub use aborting::*;
+pub use clock::{Clock, Interrupt, RealClock, SimulatedClock};
 pub use device::*;
 pub use func::*;
+pub use handle::{start, SequenceHandle};
+pub use handler::*;
+pub(crate) use handle::check_stopped;
+pub use manager::*;
+pub use runtime::*;
+pub use sandbox::*;
+pub use simulation::*;
+pub use store::*;
+pub use trigger::*;
 use jeflog::{fail, warn};
-use pyo3::{create_exception, pymodule, types::PyModule, wrap_pyfunction, Py, PyObject, PyResult, Python};
+use pyo3::{create_exception, pymodule, types::PyModule, wrap_pyfunction, Py, PyResult, Python};
 pub use unit::*;
+pub use validate::*;
+pub use watchdog::{OverrunAction, Watchdog};
 
-use crate::comm::{NodeMapping, SensorType, Sequence, ValveState};
-use std::sync::{Arc, Mutex, OnceLock};
+use crate::comm::{CompositeValveState, LogLevel, LogRecord, Measurement, NodeMapping, Sequence, SequenceError, ValveState};
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, Once};
 
 #[pymodule]
 fn sequences(py: Python<'_>, module: &PyModule) -> PyResult<()> {
@@ -33,107 +63,135 @@ fn sequences(py: Python<'_>, module: &PyModule) -> PyResult<()> {
 
 	module.add_class::<Sensor>()?;
 	module.add_class::<Valve>()?;
+	module.add_class::<ValveState>()?;
+	module.add_class::<CompositeValveState>()?;
+	module.add_class::<LogLevel>()?;
 	module.add_class::<IntervalIterator>()?;
 
 	module.add_function(wrap_pyfunction!(wait_for, module)?)?;
 	module.add_function(wrap_pyfunction!(wait_until, module)?)?;
 	module.add_function(wrap_pyfunction!(abort, module)?)?;
 	module.add_function(wrap_pyfunction!(interval, module)?)?;
+	module.add_function(wrap_pyfunction!(log, module)?)?;
+
+	module.add("AbortError", py.get_type::<AbortError>())?;
+	module.add("StopError", py.get_type::<StopError>())?;
+
+	module.add("DeviceError", py.get_type::<exceptions::DeviceError>())?;
+	module.add("UnknownDeviceError", py.get_type::<exceptions::UnknownDeviceError>())?;
+	module.add("DeviceOfflineError", py.get_type::<exceptions::DeviceOfflineError>())?;
+	module.add("DeviceUnavailableError", py.get_type::<exceptions::DeviceUnavailableError>())?;
+	module.add("DeviceRemovedError", py.get_type::<exceptions::DeviceRemovedError>())?;
+	module.add("ActuationRejectedError", py.get_type::<exceptions::ActuationRejectedError>())?;
+	module.add("ActuationTimeoutError", py.get_type::<exceptions::ActuationTimeoutError>())?;
+	module.add("SandboxError", py.get_type::<exceptions::SandboxError>())?;
 
 	Ok(())
 }
 
-create_exception!(sequences, AbortError, pyo3::exceptions::PyException);
-
-// let's break this one down:
-// Mutex<...> - required because this is a global variable, so needed to implement Sync and be used across threads safely
-// Option<...> - before initialization by set_device_handler, this will be None, so necessary for compiler to be happy
-// Box<dyn ...> - wraps the enclosed dynamic type on the heap, because it's exact size and type are unknown at compile-time
-// Fn(&str, DeviceAction) -> Option<Measurement> - the trait bound of the type of the closure being stored, with its arguments and return value
-// + Send - requires that everything captured in the closure be safe to send across threads
-pub(crate) static DEVICE_HANDLER: Mutex<Option<Box<dyn Fn(&str, DeviceAction) -> PyObject + Send>>> = Mutex::new(None);
-pub(crate) static MAPPINGS: OnceLock<Arc<Mutex<Vec<NodeMapping>>>> = OnceLock::new();
+// both exceptions below derive from BaseException rather than Exception, like KeyboardInterrupt, so
+// that a sequence catching every exception with `except Exception` cannot accidentally swallow them.
+create_exception!(
+	sequences,
+	AbortError,
+	pyo3::exceptions::PyBaseException,
+	"Raised inside every running sequence when the vehicle is aborted."
+);
+
+create_exception!(
+	sequences,
+	StopError,
+	pyo3::exceptions::PyBaseException,
+	"Raised inside a sequence when it is stopped by its SequenceHandle."
+);
+
+/// Set once the default runtime has been given its mappings by `initialize`.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Registers the `sequences` module and starts the Python interpreter, exactly once per process.
+pub(crate) fn initialize_python() {
+	static INITIALIZE: Once = Once::new();
+
+	INITIALIZE.call_once(|| {
+		pyo3::append_to_inittab!(sequences);
+		pyo3::prepare_freethreaded_python();
+
+		Python::with_gil(|py| {
+			if let Err(error) = output::redirect(py) {
+				fail!("Failed to capture the output of sequences: {error}");
+			}
+		});
+	});
+}
 
-/// Initializes the sequences portion of the library.
+/// Initializes the sequences portion of the library, giving the default runtime its mappings.
 pub fn initialize(mappings: Arc<Mutex<Vec<NodeMapping>>>) {
-	if MAPPINGS.set(mappings).is_err() {
+	if INITIALIZED.swap(true, Ordering::SeqCst) {
 		warn!("Sequences library has already been initialized. Ignoring reinitialization.");
 		return;
 	}
 
-	pyo3::append_to_inittab!(sequences);
-	pyo3::prepare_freethreaded_python();
+	SequenceRuntime::default_runtime().set_mappings(mappings);
 }
 
-/// Given to the device handler to instruct it to perform a type of action.
-pub enum DeviceAction {
-	/// Instructs to read and return a sensor value.
-	ReadSensor,
-
-	/// Instructs to read the actual estimated valve state (as a string for now).
-	ReadValveState,
+/// Initializes the sequences portion of the library, persisting the abort sequence with the given store.
+///
+/// The abort sequence saved in the store is loaded and verified against its checksum. If there is no
+/// valid abort sequence, the library is still initialized, but the error is returned so that the
+/// operator can be told that an abort sequence must be sent before the vehicle can be safely aborted.
+pub fn initialize_with_abort_store(mappings: Arc<Mutex<Vec<NodeMapping>>>, store: AbortStore) -> Result<(), AbortStoreError> {
+	initialize(mappings);
+
+	aborting::load_abort_store(store).inspect_err(|error| {
+		fail!("Failed to load abort sequence: {error}");
+	})
+}
 
-	/// Instructs to command a valve actuation to match the given state.
-	ActuateValve {
-		/// The state which the valve should be actuated to match, either `Open` or `Closed`.
-		state: ValveState
-	},
+/// Propagates updated mappings into every sequence running in the default runtime, which must be
+/// called after the mappings given to `initialize` are changed.
+pub fn reload_mappings() {
+	SequenceRuntime::default_runtime().reload_mappings();
+}
 
-	/// Instructs to abort all sequences and run the saved abort sequence.
-	Abort,
+/// Sets the device handler of the default runtime, which interacts with external boards from the flight computer code.
+pub fn set_device_handler(handler: impl DeviceHandler + 'static) {
+	SequenceRuntime::default_runtime().set_device_handler(handler);
 }
 
-/// Sets the device handler callback, which interacts with external boards from the flight computer code.
-/// 
-/// The first argument of this callback is a `&str` which is the name of the target device (typically a valve or sensor),
-/// and the second argument is the action to be performed by the handler. The return value is an `Option<Measurement>` because in
-/// the event of a read, a measurement will need to be returned, but a valve actuation requires no return.
-pub fn set_device_handler(handler: impl Fn(&str, DeviceAction) -> PyObject + Send + 'static) {
-	let Ok(mut device_handler) = DEVICE_HANDLER.lock() else {
-		fail!("Failed to lock global device handler: Mutex is poisoned.");
-		return;
-	};
+/// Sets the sandbox restricting sequences run by the default runtime, or lifts it if `None`.
+pub fn set_sandbox(sandbox: Option<Sandbox>) {
+	SequenceRuntime::default_runtime().set_sandbox(sandbox);
+}
 
-	*device_handler = Some(Box::new(handler));
+/// Sets the time budgets enforced on sequences and triggers run by the default runtime.
+pub fn set_watchdog(watchdog: Watchdog) {
+	SequenceRuntime::default_runtime().set_watchdog(watchdog);
 }
 
-// TODO: change the run function to return an error in the event of one instead of printing out the error
+/// Sets the callback which receives every message logged by sequences run by the default runtime.
+pub fn set_log_handler(handler: impl Fn(LogRecord) + Send + Sync + 'static) {
+	SequenceRuntime::default_runtime().set_log_handler(handler);
+}
 
-/// Runs a sequence. The `initialize` function must be called before this.
-pub fn run(sequence: Sequence) {
-	let Some(mappings) = MAPPINGS.get() else {
-		fail!("Sequences library has not been initialized. Call the initialize function before running a sequence.");
-		return;
-	};
+/// Records a reading of a sensor in the history of the default runtime, from which sequences compute
+/// statistics such as `Sensor.average`.
+pub fn record_reading(name: &str, measurement: &Measurement) {
+	SequenceRuntime::default_runtime().record_reading(name, measurement);
+}
 
-	let Ok(mappings) = mappings.lock() else {
-		fail!("Mappings could not be locked within common::sequence::run.");
-		return;
-	};
-
-	Python::with_gil(|py| {
-		if let Err(error) = py.run("from sequences import *", None, None) {
-			fail!("Failed to import sequences library: {error}");
-			return;
-		}
-
-		for mapping in &*mappings {
-			let definition = match mapping.sensor_type {
-				SensorType::Valve => format!("{0} = Valve('{0}')", mapping.text_id),
-				_ => format!("{0} = Sensor('{0}')", mapping.text_id),
-			};
-
-			if let Err(error) = py.run(&definition, None, None) {
-				fail!("Failed to define '{}' as a mapping: {error}", mapping.text_id);
-				return;
-			}
-		}
+/// Sets the clock used by sequences run by the default runtime, replacing the real clock.
+pub fn set_clock(clock: impl Clock + 'static) {
+	SequenceRuntime::default_runtime().set_clock(clock);
+}
 
-		// drop the lock before entering script to prevent deadlock
-		drop(mappings);
+/// Runs a sequence on the calling thread using the default runtime. The `initialize` function must be called before this.
+///
+/// Returns the reason the sequence failed if it did not run to completion, which includes any
+/// exception raised by the script, so it can be reported back to the control server.
+pub fn run(sequence: Sequence) -> Result<(), SequenceError> {
+	if !INITIALIZED.load(Ordering::SeqCst) {
+		return Err(SequenceError::Uninitialized);
+	}
 
-		if let Err(error) = py.run(&sequence.script, None, None) {
-			fail!("Failed to run sequence '{}': {error}", sequence.name);
-		}
-	});
+	SequenceRuntime::default_runtime().run(sequence)
 }
@@ -0,0 +1,173 @@
+use std::{cell::RefCell, time::{SystemTime, UNIX_EPOCH}};
+
+use jeflog::{fail, pass, warn};
+use pyo3::{exceptions::PyAttributeError, pyclass, pymethods, Py, PyObject, PyResult, Python};
+
+use crate::comm::{LogLevel, LogRecord};
+use super::SequenceRuntime;
+
+thread_local! {
+	/// Where the sequence running on the current thread sends its output, if any.
+	static CURRENT_OUTPUT: RefCell<Option<Output>> = const { RefCell::new(None) };
+}
+
+/// The output of a running sequence, holding partial lines until they are complete.
+#[derive(Debug)]
+struct Output {
+	sequence: String,
+	runtime: SequenceRuntime,
+	stdout: String,
+	stderr: String,
+}
+
+impl Output {
+	fn record(&self, level: LogLevel, message: String) -> LogRecord {
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map_or(0.0, |since| since.as_secs_f64());
+
+		LogRecord {
+			sequence: self.sequence.clone(),
+			timestamp,
+			level,
+			message,
+		}
+	}
+
+	/// Buffers text written to stdout or stderr, returning a record of each line once it is complete.
+	fn write(&mut self, stderr: bool, text: &str) -> Vec<LogRecord> {
+		let (buffer, level) = if stderr {
+			(&mut self.stderr, LogLevel::Error)
+		} else {
+			(&mut self.stdout, LogLevel::Info)
+		};
+
+		buffer.push_str(text);
+
+		let Some(end) = buffer.rfind('\n') else {
+			return Vec::new();
+		};
+
+		let lines = buffer.drain(..=end).collect::<String>();
+
+		lines
+			.lines()
+			.map(|line| self.record(level, line.to_owned()))
+			.collect()
+	}
+
+	/// Logs any partial lines left when the sequence finishes.
+	fn flush(mut self) {
+		let stdout = std::mem::take(&mut self.stdout);
+		let stderr = std::mem::take(&mut self.stderr);
+
+		for (level, message) in [(LogLevel::Info, stdout), (LogLevel::Error, stderr)] {
+			if !message.is_empty() {
+				self.runtime.log(self.record(level, message));
+			}
+		}
+	}
+}
+
+/// Runs the closure with the output of the calling thread attributed to the named sequence.
+pub(crate) fn capture<T>(runtime: &SequenceRuntime, sequence: &str, f: impl FnOnce() -> T) -> T {
+	let previous = CURRENT_OUTPUT.replace(Some(Output {
+		sequence: sequence.to_owned(),
+		runtime: runtime.clone(),
+		stdout: String::new(),
+		stderr: String::new(),
+	}));
+
+	let result = f();
+
+	if let Some(output) = CURRENT_OUTPUT.replace(previous) {
+		output.flush();
+	}
+
+	result
+}
+
+/// Logs a message from the sequence running on the calling thread, or from the flight computer itself
+/// if no sequence is running.
+pub(crate) fn log(level: LogLevel, message: &str) {
+	// the record is delivered after releasing the output, as the log handler may itself write output
+	let record = CURRENT_OUTPUT.with_borrow(|output| {
+		output
+			.as_ref()
+			.map(|output| (output.runtime.clone(), output.record(level, message.to_owned())))
+	});
+
+	match record {
+		Some((runtime, record)) => runtime.log(record),
+		None => log_locally(level, message),
+	}
+}
+
+/// Logs a message to the terminal of the flight computer, used when a runtime has no log handler.
+pub(crate) fn log_locally(level: LogLevel, message: &str) {
+	match level {
+		LogLevel::Debug | LogLevel::Info => pass!("{message}"),
+		LogLevel::Warning => warn!("{message}"),
+		LogLevel::Error => fail!("{message}"),
+	}
+}
+
+/// Replaces Python's `sys.stdout` and `sys.stderr`, attributing writes to the sequence running on the
+/// writing thread and passing writes from anywhere else through to the original stream.
+///
+/// The replacement is process-wide, so every other attribute, such as `encoding`, `isatty()` or
+/// `fileno()`, is forwarded to the original stream for the benefit of code outside of sequences.
+#[pyclass]
+struct Stream {
+	stderr: bool,
+	original: PyObject,
+}
+
+#[pymethods]
+impl Stream {
+	fn write(&self, py: Python<'_>, text: &str) -> PyResult<usize> {
+		let captured = CURRENT_OUTPUT.with_borrow_mut(|output| {
+			output
+				.as_mut()
+				.map(|output| (output.runtime.clone(), output.write(self.stderr, text)))
+		});
+
+		match captured {
+			Some((runtime, records)) => records.into_iter().for_each(|record| runtime.log(record)),
+			None if !self.original.is_none(py) => {
+				self.original.call_method1(py, "write", (text,))?;
+			},
+			None => {},
+		}
+
+		Ok(text.chars().count())
+	}
+
+	fn flush(&self, py: Python<'_>) -> PyResult<()> {
+		if !self.original.is_none(py) {
+			self.original.call_method0(py, "flush")?;
+		}
+
+		Ok(())
+	}
+
+	fn __getattr__(&self, py: Python<'_>, name: &str) -> PyResult<PyObject> {
+		if self.original.is_none(py) {
+			return Err(PyAttributeError::new_err(format!("'Stream' object has no attribute '{name}'")));
+		}
+
+		self.original.getattr(py, name)
+	}
+}
+
+/// Redirects `sys.stdout` and `sys.stderr` so that the output of each sequence is captured.
+pub(crate) fn redirect(py: Python<'_>) -> PyResult<()> {
+	let sys = py.import("sys")?;
+
+	for (name, stderr) in [("stdout", false), ("stderr", true)] {
+		let original = sys.getattr(name)?.into();
+		sys.setattr(name, Py::new(py, Stream { stderr, original })?)?;
+	}
+
+	Ok(())
+}
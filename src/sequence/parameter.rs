@@ -0,0 +1,99 @@
+use pyo3::{types::PyDict, IntoPy, PyObject, PyResult, Python};
+
+use crate::comm::{Measurement, NodeMapping, Parameter, ParameterKind, ParameterValue, Sequence, SequenceError};
+use super::error;
+
+/// Checks the arguments of a sequence against its parameters, then defines each parameter as a global
+/// of the namespace the script runs in.
+///
+/// Parameters must be named by Python identifiers which are not keywords, and must not shadow any of
+/// the given mappings, an export of the `sequences` library, or a builtin.
+pub(crate) fn bind(py: Python<'_>, sequence: &Sequence, namespace: &PyDict, mappings: &[NodeMapping]) -> Result<(), SequenceError> {
+	if let Some(name) = sequence.arguments.keys().find(|name| !sequence.parameters.iter().any(|parameter| &parameter.name == *name)) {
+		return Err(invalid(name, "the sequence has no parameter with this name"));
+	}
+
+	for parameter in &sequence.parameters {
+		check_name(py, &parameter.name, mappings)?;
+
+		let value = resolve(py, parameter, sequence.arguments.get(&parameter.name))?;
+
+		namespace
+			.set_item(&parameter.name, value)
+			.map_err(|error| SequenceError::Exception(error::python_exception(py, &error)))?;
+	}
+
+	Ok(())
+}
+
+/// Converts the argument given to a parameter, or its default if none was given, to a Python value.
+fn resolve(py: Python<'_>, parameter: &Parameter, argument: Option<&ParameterValue>) -> Result<PyObject, SequenceError> {
+	let Some(value) = argument.or(parameter.default.as_ref()) else {
+		return Err(invalid(&parameter.name, "no argument was given and the parameter has no default"));
+	};
+
+	let value = match (parameter.kind, value) {
+		(ParameterKind::Boolean, ParameterValue::Boolean(value)) => value.into_py(py),
+		(ParameterKind::Integer, ParameterValue::Integer(value)) => value.into_py(py),
+		(ParameterKind::Number, ParameterValue::Number(value)) => value.into_py(py),
+		(ParameterKind::Number, &ParameterValue::Integer(value)) => (value as f64).into_py(py),
+		(ParameterKind::Text, ParameterValue::Text(value)) => value.into_py(py),
+		(ParameterKind::Quantity(unit), &ParameterValue::Number(value)) => Measurement { value, unit }.into_py(py),
+		(ParameterKind::Quantity(unit), &ParameterValue::Integer(value)) => Measurement { value: value as f64, unit }.into_py(py),
+		(kind, value) => return Err(invalid(&parameter.name, &format!("expected a {kind} but was given {value:?}"))),
+	};
+
+	Ok(value)
+}
+
+/// Checks that a parameter name can be referred to by the script without hiding anything else it may use.
+fn check_name(py: Python<'_>, name: &str, mappings: &[NodeMapping]) -> Result<(), SequenceError> {
+	if !is_identifier(name) {
+		return Err(invalid(name, "the name is not a valid Python identifier"));
+	}
+
+	let reason = reserved(py, name)
+		.map_err(|error| SequenceError::Exception(error::python_exception(py, &error)))?;
+
+	if let Some(reason) = reason {
+		return Err(invalid(name, reason));
+	}
+
+	if mappings.iter().any(|mapping| mapping.text_id == name) {
+		return Err(invalid(name, "the name shadows a mapping"));
+	}
+
+	Ok(())
+}
+
+/// Determines why a name is reserved by Python or the `sequences` library, if it is.
+fn reserved(py: Python<'_>, name: &str) -> PyResult<Option<&'static str>> {
+	if py.import("keyword")?.call_method1("iskeyword", (name,))?.is_true()? {
+		return Ok(Some("the name is a Python keyword"));
+	}
+
+	if py.import("sequences")?.hasattr(name)? {
+		return Ok(Some("the name shadows an export of the sequences library"));
+	}
+
+	if py.import("builtins")?.hasattr(name)? {
+		return Ok(Some("the name shadows a Python builtin"));
+	}
+
+	Ok(None)
+}
+
+/// Determines if a name can be used as a Python global.
+fn is_identifier(name: &str) -> bool {
+	let mut chars = name.chars();
+
+	chars.next().is_some_and(|first| first.is_alphabetic() || first == '_')
+		&& chars.all(|char| char.is_alphanumeric() || char == '_')
+}
+
+fn invalid(parameter: &str, reason: &str) -> SequenceError {
+	SequenceError::InvalidArgument {
+		parameter: parameter.to_owned(),
+		reason: reason.to_owned(),
+	}
+}
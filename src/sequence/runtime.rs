@@ -0,0 +1,472 @@
+use std::{cell::RefCell, collections::HashSet, fmt, sync::{atomic::AtomicBool, Arc, Mutex, OnceLock}};
+
+use jeflog::fail;
+use pyo3::{types::PyDict, AsPyPointer, Py, Python};
+
+use crate::comm::{LogRecord, Measurement, NodeMapping, SensorType, Sequence, SequenceError};
+use super::{aborting, clock::{Clock, RealClock}, error, handle::{self, LiveTokens, SequenceHandle, StopToken}, history::{History, Window}, initialize_python, output, parameter, watchdog::Guard, DeviceError, DeviceHandler, Sandbox, Watchdog};
+
+thread_local! {
+	/// The runtime of the sequence or trigger condition running on the current thread, if any.
+	static CURRENT_RUNTIME: RefCell<Option<SequenceRuntime>> = const { RefCell::new(None) };
+}
+
+/// The runtime used by the free functions of this module, such as `run` and `set_device_handler`.
+static DEFAULT_RUNTIME: OnceLock<SequenceRuntime> = OnceLock::new();
+
+/// The globals with which a sequence is run.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Namespace {
+	/// A fresh namespace containing only the `sequences` library and mappings, discarded once the run ends.
+	#[default]
+	Isolated,
+
+	/// The namespace of the runtime, which persists between every run which requests it.
+	Persistent,
+}
+
+/// Receives the messages logged by sequences, such as to forward them to the GUI.
+type LogHandler = Arc<dyn Fn(LogRecord) + Send + Sync>;
+
+struct RuntimeState {
+	handler: Mutex<Option<Box<dyn DeviceHandler>>>,
+	mappings: Mutex<Arc<Mutex<Vec<NodeMapping>>>>,
+	clock: Mutex<Arc<dyn Clock>>,
+	sandbox: Mutex<Option<Sandbox>>,
+	watchdog: Mutex<Watchdog>,
+	log_handler: Mutex<Option<LogHandler>>,
+	history: Mutex<History>,
+	namespace: Py<PyDict>,
+
+	/// The namespaces of every sequence currently running, which receive mapping updates.
+	active: Mutex<Vec<Py<PyDict>>>,
+
+	/// The mappings as of the last reload, against which updated mappings are compared.
+	defined: Mutex<Vec<NodeMapping>>,
+
+	/// The names of devices which were removed from the mappings by a reload.
+	removed: Mutex<HashSet<String>>,
+
+	/// The stop tokens of every sequence alive in this runtime, which are signalled by an abort.
+	tokens: LiveTokens,
+
+	/// Set while an abort of this runtime is in progress, so that concurrent aborts run the abort
+	/// sequence only once.
+	aborting: AtomicBool,
+}
+
+/// An environment in which sequences run, owning its device handler, mappings, clock and Python namespace.
+///
+/// Sequences and trigger conditions run by a runtime only access devices through that runtime's
+/// handler, so several runtimes may run side by side in one process, such as in parallel tests.
+/// Each run gets its own namespace unless the persistent namespace of the runtime is requested.
+/// Cloning a `SequenceRuntime` yields another handle to the same environment.
+#[derive(Clone)]
+pub struct SequenceRuntime {
+	inner: Arc<RuntimeState>,
+}
+
+impl fmt::Debug for SequenceRuntime {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("SequenceRuntime")
+			.field("mappings", &self.mappings())
+			.field("clock", &self.clock())
+			.finish_non_exhaustive()
+	}
+}
+
+impl SequenceRuntime {
+	/// Constructs a runtime using the given mappings, which may be updated while the runtime is in use.
+	///
+	/// The runtime has no device handler until one is set, uses the real clock, and neither sandboxes
+	/// nor budgets its sequences.
+	pub fn new(mappings: Arc<Mutex<Vec<NodeMapping>>>) -> Self {
+		initialize_python();
+
+		let namespace = Python::with_gil(|py| Py::from(PyDict::new(py)));
+		let defined = mappings.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+
+		SequenceRuntime {
+			inner: Arc::new(RuntimeState {
+				handler: Mutex::new(None),
+				mappings: Mutex::new(mappings),
+				clock: Mutex::new(Arc::new(RealClock)),
+				sandbox: Mutex::new(None),
+				watchdog: Mutex::new(Watchdog::default()),
+				log_handler: Mutex::new(None),
+				history: Mutex::new(History::default()),
+				namespace,
+				active: Mutex::new(Vec::new()),
+				defined: Mutex::new(defined),
+				removed: Mutex::new(HashSet::new()),
+				tokens: LiveTokens::default(),
+				aborting: AtomicBool::new(false),
+			}),
+		}
+	}
+
+	/// Sets the device handler through which sequences run by this runtime interact with external boards.
+	pub fn set_device_handler(&self, handler: impl DeviceHandler + 'static) {
+		*self.inner.handler.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Box::new(handler));
+	}
+
+	/// Sets the clock used by sequences run by this runtime.
+	pub fn set_clock(&self, clock: impl Clock + 'static) {
+		*self.inner.clock.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(clock);
+	}
+
+	/// Sets the sandbox restricting the sequences and trigger conditions run by this runtime, or lifts
+	/// it if `None`. Sequences which are already running are unaffected.
+	pub fn set_sandbox(&self, sandbox: Option<Sandbox>) {
+		*self.inner.sandbox.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = sandbox;
+	}
+
+	/// Gets the sandbox restricting sequences run by this runtime, if any.
+	pub fn sandbox(&self) -> Option<Sandbox> {
+		self.inner.sandbox
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+			.clone()
+	}
+
+	/// Sets the time budgets enforced on the sequences and trigger conditions run by this runtime.
+	/// Sequences which are already running keep the budget they started with.
+	pub fn set_watchdog(&self, watchdog: Watchdog) {
+		*self.inner.watchdog.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = watchdog;
+	}
+
+	/// Gets the time budgets enforced on the sequences and trigger conditions run by this runtime.
+	pub fn watchdog(&self) -> Watchdog {
+		*self.inner.watchdog.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	/// Sets the callback which receives every message logged by sequences run by this runtime, whether
+	/// through `log` or by writing to stdout or stderr. Without one, messages are logged to the terminal.
+	pub fn set_log_handler(&self, handler: impl Fn(LogRecord) + Send + Sync + 'static) {
+		*self.inner.log_handler.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(handler));
+	}
+
+	/// Delivers a message logged by a sequence to the log handler of this runtime.
+	pub(crate) fn log(&self, record: LogRecord) {
+		// the handler is called without holding the lock, so that it may log or replace itself
+		let handler = self.inner.log_handler
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+			.clone();
+
+		match handler {
+			Some(handler) => handler(record),
+			None => output::log_locally(record.level, &format!("[{}] {}", record.sequence, record.message)),
+		}
+	}
+
+	/// Records a reading of a sensor in the history of this runtime, from which sequences compute
+	/// statistics such as `Sensor.average`.
+	///
+	/// Every reading taken by a sequence is recorded automatically, but recording the readings of the
+	/// vehicle as they arrive gives sequences a complete history.
+	pub fn record_reading(&self, name: &str, measurement: &Measurement) {
+		let now = self.clock().now();
+
+		self.inner.history
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+			.record(name, now, measurement);
+	}
+
+	/// Gets the readings of a sensor recorded during the given duration up to now.
+	pub(crate) fn history(&self, name: &str, duration: std::time::Duration) -> Window {
+		let now = self.clock().now();
+		let start = now.checked_sub(duration).unwrap_or(now);
+
+		self.inner.history
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+			.window(name, start, now)
+	}
+
+	/// Replaces the mappings used by this runtime, reloading them into every running sequence.
+	pub fn set_mappings(&self, mappings: Arc<Mutex<Vec<NodeMapping>>>) {
+		*self.inner.mappings.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = mappings;
+		self.reload_mappings();
+	}
+
+	/// Propagates the current contents of the mappings into the namespace of every running sequence
+	/// and the persistent namespace, without restarting any sequence.
+	///
+	/// This must be called after the shared mappings are updated, such as by a
+	/// `FlightControlMessage::Mappings`. New and changed mappings are defined in each namespace, while
+	/// using a `Sensor` or `Valve` whose mapping was removed raises a `DeviceRemovedError`.
+	pub fn reload_mappings(&self) {
+		let mappings = self.mappings()
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+			.clone();
+
+		let previous = std::mem::replace(
+			&mut *self.inner.defined.lock().unwrap_or_else(|poisoned| poisoned.into_inner()),
+			mappings.clone(),
+		);
+
+		let changed = mappings
+			.iter()
+			.filter(|mapping| !previous.contains(mapping))
+			.collect::<Vec<_>>();
+
+		{
+			let mut removed = self.inner.removed.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+			for mapping in &previous {
+				if !mappings.iter().any(|current| current.text_id == mapping.text_id) {
+					removed.insert(mapping.text_id.clone());
+				}
+			}
+
+			for mapping in &mappings {
+				removed.remove(&mapping.text_id);
+			}
+		}
+
+		if changed.is_empty() {
+			return;
+		}
+
+		Python::with_gil(|py| {
+			let active = self.inner.active
+				.lock()
+				.unwrap_or_else(|poisoned| poisoned.into_inner())
+				.iter()
+				.map(|namespace| namespace.clone_ref(py))
+				.collect::<Vec<_>>();
+
+			for namespace in active.iter().chain([&self.inner.namespace]) {
+				for mapping in &changed {
+					if let Err(error) = define(py, namespace.as_ref(py), mapping) {
+						fail!("Failed to reload mapping into running sequence: {error}");
+					}
+				}
+			}
+		});
+	}
+
+	/// Determines if the device with the given name was removed from the mappings by a reload.
+	pub(crate) fn is_removed(&self, name: &str) -> bool {
+		self.inner.removed
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+			.contains(name)
+	}
+
+	/// The mappings used by this runtime.
+	pub fn mappings(&self) -> Arc<Mutex<Vec<NodeMapping>>> {
+		self.inner.mappings
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+			.clone()
+	}
+
+	/// Gets the clock used by sequences run by this runtime.
+	pub(crate) fn clock(&self) -> Arc<dyn Clock> {
+		self.inner.clock
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+			.clone()
+	}
+
+	/// Determines if two handles refer to the same runtime.
+	pub fn ptr_eq(&self, other: &SequenceRuntime) -> bool {
+		Arc::ptr_eq(&self.inner, &other.inner)
+	}
+
+	/// Constructs a stop token for a sequence run by this runtime, which is signalled if it is aborted.
+	pub(crate) fn token(&self) -> StopToken {
+		self.inner.tokens.token()
+	}
+
+	/// Signals every sequence alive in this runtime to abort.
+	pub(crate) fn abort_sequences(&self) {
+		self.inner.tokens.abort_all();
+	}
+
+	/// The flag set while an abort of this runtime is in progress.
+	pub(crate) fn aborting(&self) -> &AtomicBool {
+		&self.inner.aborting
+	}
+
+	/// Aborts every sequence and trigger of this runtime, then runs the stored abort sequence in it.
+	///
+	/// Sequences and triggers of other runtimes are unaffected. See `run_abort` for details.
+	pub fn abort(&self) -> Option<SequenceHandle> {
+		aborting::abort_runtime(self)
+	}
+
+	/// Tells the device handler of this runtime, if any, that the vehicle is being aborted.
+	pub(crate) fn notify_abort(&self) {
+		if let Some(handler) = &*self.inner.handler.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+			handler.abort();
+		}
+	}
+
+	/// Runs the closure with the device handler of this runtime, failing if none has been set.
+	pub(crate) fn with_device_handler<T>(&self, f: impl FnOnce(&dyn DeviceHandler) -> Result<T, DeviceError>) -> Result<T, DeviceError> {
+		let handler = self.inner.handler.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+		let Some(handler) = &*handler else {
+			fail!("Device handler not set before accessing external device.");
+			return Err(DeviceError::NoHandler);
+		};
+
+		f(handler.as_ref())
+	}
+
+	/// Runs the closure with this runtime as the runtime of the calling thread, restoring the
+	/// previous runtime afterwards.
+	pub(crate) fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+		let previous = CURRENT_RUNTIME.replace(Some(self.clone()));
+		let result = f();
+		CURRENT_RUNTIME.set(previous);
+		result
+	}
+
+	/// Imports the `sequences` library into the given namespace and defines every mapping as a `Sensor`
+	/// or `Valve`, returning the namespace.
+	pub(crate) fn prepare<'py>(&self, py: Python<'py>, namespace: Namespace) -> Result<&'py PyDict, SequenceError> {
+		// the mappings are copied out so that the lock is never held while running Python code, which
+		// may release the GIL to another thread that is itself waiting on the lock.
+		let mappings = self.mappings()
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+			.clone();
+
+		let namespace = match namespace {
+			Namespace::Isolated => PyDict::new(py),
+			Namespace::Persistent => self.inner.namespace.clone_ref(py).into_ref(py),
+		};
+
+		// without an explicit __builtins__, the namespace would be given the unrestricted builtins
+		if let Some(sandbox) = self.sandbox() {
+			sandbox.builtins(py)
+				.and_then(|builtins| namespace.set_item("__builtins__", builtins))
+				.map_err(|error| SequenceError::Import(error::python_exception(py, &error)))?;
+		} else if namespace.contains("__builtins__").unwrap_or(false) {
+			namespace.del_item("__builtins__")
+				.map_err(|error| SequenceError::Import(error::python_exception(py, &error)))?;
+		}
+
+		py.run("from sequences import *", Some(namespace), None)
+			.map_err(|error| SequenceError::Import(error::python_exception(py, &error)))?;
+
+		for mapping in &mappings {
+			define(py, namespace, mapping)?;
+		}
+
+		Ok(namespace)
+	}
+
+	/// Runs a sequence on the calling thread in a fresh namespace.
+	///
+	/// Returns the reason the sequence failed if it did not run to completion, which includes any
+	/// exception raised by the script, so it can be reported back to the control server.
+	pub fn run(&self, sequence: Sequence) -> Result<(), SequenceError> {
+		self.run_in(sequence, Namespace::Isolated)
+	}
+
+	/// Runs a sequence on the calling thread in the given namespace.
+	pub fn run_in(&self, sequence: Sequence, namespace: Namespace) -> Result<(), SequenceError> {
+		// sequences run directly on the calling thread still need a token to be reachable by an abort
+		if handle::current_token().is_none() {
+			return handle::with_token(self.token(), || self.run_in(sequence, namespace));
+		}
+
+		self.enter(|| {
+			Python::with_gil(|py| {
+				if let Some(sandbox) = self.sandbox() {
+					sandbox.check(py, &sequence.script)
+						.map_err(|error| error::script_error(py, &error))?;
+				}
+
+				let namespace = self.prepare(py, namespace)?;
+
+				let mappings = self.mappings()
+					.lock()
+					.unwrap_or_else(|poisoned| poisoned.into_inner())
+					.clone();
+
+				parameter::bind(py, &sequence, namespace, &mappings)?;
+
+				// the namespace is registered while the sequence runs so that it receives mapping updates
+				self.inner.active
+					.lock()
+					.unwrap_or_else(|poisoned| poisoned.into_inner())
+					.push(Py::from(namespace));
+
+				let watchdog = self.watchdog();
+
+				let guard = watchdog.sequence_budget.map(|budget| {
+					Guard::new(py, &sequence.name, budget, watchdog.action, handle::current_token())
+				});
+
+				let result = output::capture(self, &sequence.name, || {
+					let result = py.run(&sequence.script, Some(namespace), None);
+
+					// the guard is finished before any other Python code runs, such as in formatting
+					// the error or flushing output, so that an interruption still pending when the
+					// script ended cannot be raised there instead.
+					let interrupted = guard.is_some_and(|guard| guard.finish(py));
+
+					result.map_err(|error| match watchdog.sequence_budget {
+						Some(budget) if interrupted => SequenceError::Overrun { budget },
+						_ => error::script_error(py, &error),
+					})
+				});
+
+				self.inner.active
+					.lock()
+					.unwrap_or_else(|poisoned| poisoned.into_inner())
+					.retain(|active| active.as_ptr() != namespace.as_ptr());
+
+				result
+			})
+		})
+	}
+
+	/// Starts running a sequence on a new thread in a fresh namespace, returning a handle which can be
+	/// used to stop it.
+	pub fn start(&self, sequence: Sequence) -> SequenceHandle {
+		self.start_in(sequence, Namespace::Isolated)
+	}
+
+	/// Starts running a sequence on a new thread in the given namespace, returning a handle which can
+	/// be used to stop it.
+	pub fn start_in(&self, sequence: Sequence, namespace: Namespace) -> SequenceHandle {
+		handle::spawn(self.clone(), sequence, namespace, self.token(), |_| {})
+	}
+
+	/// Gets the runtime of the sequence running on the calling thread, or the default runtime if the
+	/// calling thread is not running a sequence.
+	pub(crate) fn current() -> Option<SequenceRuntime> {
+		CURRENT_RUNTIME
+			.with_borrow(Clone::clone)
+			.or_else(|| DEFAULT_RUNTIME.get().cloned())
+	}
+
+	/// Gets the runtime used by the free functions of this module, creating it without any mappings
+	/// if it does not exist yet.
+	pub(crate) fn default_runtime() -> &'static SequenceRuntime {
+		DEFAULT_RUNTIME.get_or_init(|| SequenceRuntime::new(Arc::new(Mutex::new(Vec::new()))))
+	}
+}
+
+/// Defines a mapping in a namespace as a `Sensor` or `Valve` named after its text ID.
+fn define(py: Python<'_>, namespace: &PyDict, mapping: &NodeMapping) -> Result<(), SequenceError> {
+	let definition = match mapping.sensor_type {
+		SensorType::Valve => format!("{0} = Valve('{0}')", mapping.text_id),
+		_ => format!("{0} = Sensor('{0}')", mapping.text_id),
+	};
+
+	py.run(&definition, Some(namespace), None)
+		.map_err(|error| SequenceError::Mapping {
+			text_id: mapping.text_id.clone(),
+			exception: error::python_exception(py, &error),
+		})
+}
@@ -0,0 +1,159 @@
+use pyo3::{pyclass, pymethods, types::{PyDict, PyModule, PyTuple}, Py, PyAny, PyObject, PyResult, Python};
+
+use super::exceptions::SandboxError;
+
+/// The builtins available to sandboxed sequences by default, none of which touch the filesystem,
+/// network or other processes, or allow attributes to be looked up by a string.
+const DEFAULT_BUILTINS: &[&str] = &[
+	"__build_class__", "abs", "all", "any", "bin", "bool", "bytes", "callable", "chr", "classmethod",
+	"complex", "dict", "divmod", "enumerate", "filter", "float", "format", "frozenset", "hash", "hex",
+	"int", "isinstance", "issubclass", "iter", "len", "list", "map", "max", "min", "next", "object",
+	"oct", "ord", "pow", "print", "property", "range", "repr", "reversed", "round", "set", "slice",
+	"sorted", "staticmethod", "str", "sum", "super", "tuple", "zip",
+];
+
+/// The modules sandboxed sequences may import by default, none of which look up attributes by a string.
+///
+/// Modules such as `operator`, whose `attrgetter` and `methodcaller` take attribute names as strings,
+/// must never be allowed, as they reach attributes which the check on scripts cannot see.
+const DEFAULT_MODULES: &[&str] = &["cmath", "itertools", "math"];
+
+/// Restricts the builtins and modules available to sequences, so that an operator's script cannot
+/// access the filesystem, network or processes of the flight computer.
+///
+/// Builtins outside of the allowlist are replaced by stubs which raise a `SandboxError` when called,
+/// as does importing a module outside of the allowlist. Exception classes are always available, and
+/// the `sequences` library may always be imported. Because builtins such as `type` and `getattr` are
+/// withheld, accessing an attribute beginning with an underscore is also rejected before a script runs,
+/// which closes off reaching unsafe objects through introspection.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Sandbox {
+	/// The names of the builtins which sequences may use.
+	pub builtins: Vec<String>,
+
+	/// The full dotted names of the modules which sequences may import.
+	pub modules: Vec<String>,
+}
+
+impl Default for Sandbox {
+	fn default() -> Self {
+		Sandbox {
+			builtins: DEFAULT_BUILTINS.iter().map(|&name| name.to_owned()).collect(),
+			modules: DEFAULT_MODULES.iter().map(|&name| name.to_owned()).collect(),
+		}
+	}
+}
+
+impl Sandbox {
+	/// Builds the restricted `__builtins__` of a sandboxed namespace.
+	pub(crate) fn builtins<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+		let all = PyModule::import(py, "builtins")?.dict();
+		let base_exception = py.get_type::<pyo3::exceptions::PyBaseException>();
+		let restricted = PyDict::new(py);
+
+		for (name, value) in all {
+			let name = name.extract::<&str>()?;
+
+			let is_exception = value
+				.downcast::<pyo3::types::PyType>()
+				.is_ok_and(|class| class.is_subclass(base_exception).unwrap_or(false));
+
+			if is_exception || self.builtins.iter().any(|allowed| allowed == name) {
+				restricted.set_item(name, value)?;
+			} else if !name.starts_with("__") {
+				if value.is_callable() {
+					restricted.set_item(name, Py::new(py, Blocked { name: name.to_owned() })?)?;
+				} else {
+					// constants such as Ellipsis and NotImplemented
+					restricted.set_item(name, value)?;
+				}
+			}
+		}
+
+		let importer = Importer {
+			modules: self.modules.clone(),
+			import: all.get_item("__import__")?.map(Into::into).unwrap_or_else(|| py.None()),
+		};
+
+		restricted.set_item("__import__", Py::new(py, importer)?)?;
+		Ok(restricted)
+	}
+
+	/// Rejects a script which accesses any attribute beginning with an underscore, reporting the line
+	/// of the first such access.
+	///
+	/// Besides `x.attr`, class patterns such as `case object(attr=x)` in a `match` statement also look
+	/// up attributes, by the names of their keywords.
+	pub(crate) fn check(&self, py: Python<'_>, script: &str) -> PyResult<()> {
+		let ast = PyModule::import(py, "ast")?;
+		let attribute = ast.getattr("Attribute")?;
+
+		// match statements only exist from Python 3.10
+		let match_class = ast.getattr("MatchClass").ok();
+
+		for node in ast.getattr("walk")?.call1((ast.getattr("parse")?.call1((script,))?,))?.iter()? {
+			let node = node?;
+
+			let names = if node.is_instance(attribute)? {
+				vec![node.getattr("attr")?.extract::<String>()?]
+			} else if match_class.map_or(Ok(false), |class| node.is_instance(class))? {
+				node.getattr("kwd_attrs")?.extract::<Vec<String>>()?
+			} else {
+				continue;
+			};
+
+			if let Some(name) = names.iter().find(|name| name.starts_with('_')) {
+				let line = node.getattr("lineno")?.extract::<u32>()?;
+
+				return Err(SandboxError::new_err(format!(
+					"access to attribute '{name}' on line {line} is not allowed in sandboxed sequences"
+				)));
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Stands in for a builtin withheld by the sandbox, raising a `SandboxError` when called.
+#[pyclass]
+struct Blocked {
+	name: String,
+}
+
+#[pymethods]
+impl Blocked {
+	#[pyo3(signature = (*_args, **_kwargs))]
+	fn __call__(&self, _args: &PyTuple, _kwargs: Option<&PyDict>) -> PyResult<()> {
+		Err(SandboxError::new_err(format!("'{}' is not allowed in sandboxed sequences", self.name)))
+	}
+}
+
+/// Replaces `__import__` in a sandboxed namespace, only importing modules in the allowlist.
+#[pyclass]
+struct Importer {
+	modules: Vec<String>,
+	import: PyObject,
+}
+
+#[pymethods]
+impl Importer {
+	#[pyo3(signature = (name, globals = None, locals = None, fromlist = None, level = 0))]
+	fn __call__(
+		&self,
+		py: Python<'_>,
+		name: &str,
+		globals: Option<&PyAny>,
+		locals: Option<&PyAny>,
+		fromlist: Option<&PyAny>,
+		level: i32,
+	) -> PyResult<PyObject> {
+		let allowed = name == "sequences" || self.modules.iter().any(|module| module == name);
+
+		if level != 0 || !allowed {
+			return Err(SandboxError::new_err(format!("importing module '{name}' is not allowed in sandboxed sequences")));
+		}
+
+		self.import.call1(py, (name, globals, locals, fromlist, level))
+	}
+}
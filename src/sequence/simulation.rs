@@ -0,0 +1,342 @@
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex, MutexGuard}, time::{Duration, Instant}};
+
+use crate::comm::{CompositeValveState, Measurement, Unit, ValveState};
+use super::{Clock, DeviceError, DeviceHandler, RealClock};
+
+/// A simulated pressurized tank whose pressure changes depending on the state of its valves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulatedTank {
+	/// The pressure of the tank, in psi.
+	pub pressure: f64,
+
+	/// The pressure which the tank cannot exceed, such as the pressure of its supply, in psi.
+	pub max_pressure: f64,
+
+	/// The rate at which the tank loses pressure regardless of its valves, in psi per second.
+	pub leak_rate: f64,
+
+	/// The valves connected to the tank, each with the rate at which it changes the pressure of the
+	/// tank while open, in psi per second. A positive rate fills the tank and a negative rate vents it.
+	pub flows: Vec<(String, f64)>,
+}
+
+/// What a simulated sensor measures.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SensorSource {
+	/// Measures the pressure of the tank with the given name.
+	Tank(String),
+
+	/// Measures a constant value.
+	Constant(f64),
+}
+
+/// A simulated sensor which measures a tank or constant with added noise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulatedSensor {
+	/// What the sensor measures.
+	pub source: SensorSource,
+
+	/// The unit of the measurements taken by the sensor.
+	pub unit: Unit,
+
+	/// The standard deviation of the normally-distributed noise added to every measurement.
+	pub noise: f64,
+}
+
+/// A simulated valve which takes time to physically move after being commanded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulatedValve {
+	/// The state of the valve before it is first commanded.
+	pub initial: ValveState,
+
+	/// The time between commanding the valve and it reaching the commanded state.
+	pub actuation_delay: Duration,
+}
+
+#[derive(Debug)]
+struct ValveModel {
+	commanded: ValveState,
+	actual: ValveState,
+
+	/// The state the valve is moving to and when it will get there, if it is in transit.
+	pending: Option<(ValveState, Instant)>,
+
+	actuation_delay: Duration,
+	actuations: usize,
+}
+
+#[derive(Debug)]
+struct World {
+	tanks: HashMap<String, SimulatedTank>,
+	sensors: HashMap<String, SimulatedSensor>,
+	valves: HashMap<String, ValveModel>,
+	offline: HashSet<String>,
+	rng: XorShift,
+	updated: Instant,
+
+	/// The clock with which the simulation advances, which should be that of the runtime using it.
+	clock: Arc<dyn Clock>,
+}
+
+impl World {
+	/// Advances the simulation to the given instant, completing valve movements along the way so that
+	/// tanks are integrated piecewise between each change in valve state.
+	fn advance(&mut self, now: Instant) {
+		loop {
+			let next_transition = self.valves
+				.values()
+				.filter_map(|valve| valve.pending.map(|(_, at)| at))
+				.filter(|&at| at <= now)
+				.min();
+
+			let Some(at) = next_transition else {
+				break;
+			};
+
+			self.integrate(at);
+
+			for valve in self.valves.values_mut() {
+				if let Some((state, _)) = valve.pending.filter(|&(_, pending_at)| pending_at <= at) {
+					valve.actual = state;
+					valve.pending = None;
+				}
+			}
+		}
+
+		self.integrate(now);
+	}
+
+	/// Integrates the pressure of every tank from the last update to the given instant, assuming
+	/// that no valve changes state in between.
+	fn integrate(&mut self, until: Instant) {
+		let elapsed = until.saturating_duration_since(self.updated).as_secs_f64();
+		self.updated = self.updated.max(until);
+
+		for tank in self.tanks.values_mut() {
+			let flow = tank.flows
+				.iter()
+				.filter(|(valve, _)| self.valves.get(valve).is_some_and(|valve| valve.actual == ValveState::Open))
+				.map(|(_, rate)| rate)
+				.sum::<f64>();
+
+			tank.pressure = (tank.pressure + (flow - tank.leak_rate) * elapsed).clamp(0.0, tank.max_pressure);
+		}
+	}
+
+	/// Fails if the device is not simulated, or if it has been taken offline.
+	fn check(&self, name: &str, exists: bool) -> Result<(), DeviceError> {
+		if !exists {
+			Err(DeviceError::UnknownDevice(name.to_owned()))
+		} else if self.offline.contains(name) {
+			Err(DeviceError::Offline(name.to_owned()))
+		} else {
+			Ok(())
+		}
+	}
+
+	fn read_sensor(&mut self, name: &str) -> Result<Measurement, DeviceError> {
+		self.check(name, self.sensors.contains_key(name))?;
+		let sensor = &self.sensors[name];
+
+		let value = match &sensor.source {
+			SensorSource::Tank(tank) => self.tanks
+				.get(tank)
+				.ok_or_else(|| DeviceError::Unavailable(name.to_owned()))?
+				.pressure,
+			SensorSource::Constant(value) => *value,
+		};
+
+		let (noise, unit) = (sensor.noise, sensor.unit);
+
+		Ok(Measurement {
+			value: value + noise * self.rng.next_gaussian(),
+			unit,
+		})
+	}
+
+	fn read_valve_state(&self, name: &str) -> Result<CompositeValveState, DeviceError> {
+		self.check(name, self.valves.contains_key(name))?;
+		let valve = &self.valves[name];
+
+		Ok(CompositeValveState {
+			commanded: valve.commanded,
+			actual: valve.actual,
+		})
+	}
+
+	fn actuate(&mut self, name: &str, state: ValveState) -> Result<(), DeviceError> {
+		let now = self.clock.now();
+		self.check(name, self.valves.contains_key(name))?;
+		let valve = self.valves.get_mut(name).expect("valve existence was checked");
+
+		valve.commanded = state;
+		valve.actuations += 1;
+
+		// repeating a command to a valve already moving to that state does not restart its movement
+		if valve.actual == state {
+			valve.pending = None;
+		} else if valve.pending.is_none_or(|(pending, _)| pending != state) {
+			valve.pending = Some((state, now + valve.actuation_delay));
+		}
+
+		Ok(())
+	}
+}
+
+/// A simulated vehicle which can stand in for real hardware behind the device handler, so that
+/// sequences can be exercised end-to-end without any boards connected.
+///
+/// The simulation advances with its own clock whenever it is accessed, which should be the clock of
+/// the runtime whose sequences it serves, so that simulated devices and sequences agree on the time.
+/// Cloning a `Simulation` yields another handle to the same simulated vehicle.
+#[derive(Clone, Debug)]
+pub struct Simulation {
+	world: Arc<Mutex<World>>,
+}
+
+impl Simulation {
+	/// Constructs an empty simulation advancing in real time, seeding its sensor noise so that runs
+	/// are reproducible.
+	pub fn new(seed: u64) -> Self {
+		Simulation::with_clock(seed, RealClock)
+	}
+
+	/// Constructs an empty simulation advancing with the given clock, such as a `SimulatedClock` which
+	/// is also given to the runtime using the simulation.
+	pub fn with_clock(seed: u64, clock: impl Clock + 'static) -> Self {
+		let updated = clock.now();
+
+		Simulation {
+			world: Arc::new(Mutex::new(World {
+				tanks: HashMap::new(),
+				sensors: HashMap::new(),
+				valves: HashMap::new(),
+				offline: HashSet::new(),
+				rng: XorShift::new(seed),
+				updated,
+				clock: Arc::new(clock),
+			})),
+		}
+	}
+
+	fn world(&self) -> MutexGuard<'_, World> {
+		let mut world = self.world.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+		let now = world.clock.now();
+		world.advance(now);
+		world
+	}
+
+	/// Adds a tank with the given name, replacing any existing tank with the same name.
+	pub fn add_tank(&self, name: impl Into<String>, tank: SimulatedTank) {
+		self.world().tanks.insert(name.into(), tank);
+	}
+
+	/// Adds a sensor with the given name, replacing any existing sensor with the same name.
+	pub fn add_sensor(&self, name: impl Into<String>, sensor: SimulatedSensor) {
+		self.world().sensors.insert(name.into(), sensor);
+	}
+
+	/// Adds a valve with the given name, replacing any existing valve with the same name.
+	pub fn add_valve(&self, name: impl Into<String>, valve: SimulatedValve) {
+		self.world().valves.insert(name.into(), ValveModel {
+			commanded: valve.initial,
+			actual: valve.initial,
+			pending: None,
+			actuation_delay: valve.actuation_delay,
+			actuations: 0,
+		});
+	}
+
+	/// Gets the current pressure of the tank with the given name, in psi, without noise.
+	pub fn pressure(&self, tank: &str) -> Option<f64> {
+		self.world()
+			.tanks
+			.get(tank)
+			.map(|tank| tank.pressure)
+	}
+
+	/// Gets the last commanded state of the valve with the given name.
+	pub fn commanded(&self, valve: &str) -> Option<ValveState> {
+		self.world()
+			.valves
+			.get(valve)
+			.map(|valve| valve.commanded)
+	}
+
+	/// Gets the physical state of the valve with the given name, which lags the commanded state by
+	/// the valve's actuation delay.
+	pub fn actual(&self, valve: &str) -> Option<ValveState> {
+		self.world()
+			.valves
+			.get(valve)
+			.map(|valve| valve.actual)
+	}
+
+	/// Gets the number of times the valve with the given name has been commanded.
+	pub fn actuations(&self, valve: &str) -> Option<usize> {
+		self.world()
+			.valves
+			.get(valve)
+			.map(|valve| valve.actuations)
+	}
+
+	/// Takes the board of a simulated device offline or brings it back online. Reading or actuating an
+	/// offline device fails with `DeviceError::Offline`, raising a `DeviceOfflineError` in the calling
+	/// sequence, and leaves the device as it was.
+	pub fn set_offline(&self, device: &str, offline: bool) {
+		let mut world = self.world();
+
+		if offline {
+			world.offline.insert(device.to_owned());
+		} else {
+			world.offline.remove(device);
+		}
+	}
+
+	/// Sets this simulation as the device handler, so that every sequence interacts with it.
+	pub fn install(&self) {
+		super::set_device_handler(self.clone());
+	}
+}
+
+impl DeviceHandler for Simulation {
+	fn read_sensor(&self, name: &str) -> Result<Measurement, DeviceError> {
+		self.world().read_sensor(name)
+	}
+
+	fn read_valve_state(&self, name: &str) -> Result<CompositeValveState, DeviceError> {
+		self.world().read_valve_state(name)
+	}
+
+	fn actuate_valve(&self, name: &str, state: ValveState) -> Result<(), DeviceError> {
+		self.world().actuate(name, state)
+	}
+}
+
+/// A small, seedable pseudorandom number generator used for sensor noise.
+#[derive(Debug)]
+struct XorShift {
+	state: u64,
+}
+
+impl XorShift {
+	fn new(seed: u64) -> Self {
+		// a state of zero would only ever produce zeroes
+		XorShift { state: seed.max(1) }
+	}
+
+	/// Generates a number uniformly distributed in (0, 1].
+	fn next_uniform(&mut self) -> f64 {
+		self.state ^= self.state << 13;
+		self.state ^= self.state >> 7;
+		self.state ^= self.state << 17;
+
+		((self.state >> 11) + 1) as f64 / (1u64 << 53) as f64
+	}
+
+	/// Generates a number from the standard normal distribution using the Box-Muller transform.
+	fn next_gaussian(&mut self) -> f64 {
+		let (u1, u2) = (self.next_uniform(), self.next_uniform());
+		(-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+	}
+}
@@ -0,0 +1,129 @@
+use std::{collections::HashMap, error::Error, fmt, fs, io::{self, Write}, path::{Path, PathBuf}};
+
+use crate::comm::{fnv1a, Sequence};
+
+/// The name of the abort sequence, which is the only sequence persisted by an `AbortStore`.
+const ABORT_NAME: &str = "abort";
+
+/// An error encountered while saving or loading the abort sequence.
+#[derive(Debug)]
+pub enum AbortStoreError {
+	/// No abort sequence has been saved at the contained path.
+	Missing(PathBuf),
+
+	/// The abort sequence file could not be read or written.
+	Io(io::Error),
+
+	/// The abort sequence file does not have the expected format.
+	Malformed(PathBuf),
+
+	/// The abort sequence file was read, but its contents do not match the checksum saved with it.
+	ChecksumMismatch {
+		/// The checksum saved alongside the script.
+		expected: u64,
+
+		/// The checksum of the script which was actually read.
+		actual: u64,
+	},
+}
+
+impl fmt::Display for AbortStoreError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Missing(path) => write!(f, "no abort sequence is stored at {}, so an abort will not run one until it is sent", path.display()),
+			Self::Io(error) => write!(f, "failed to access stored abort sequence: {error}"),
+			Self::Malformed(path) => write!(f, "stored abort sequence at {} is malformed", path.display()),
+			Self::ChecksumMismatch { expected, actual } => write!(f, "stored abort sequence is corrupt: expected checksum {expected:016x} but found {actual:016x}"),
+		}
+	}
+}
+
+impl Error for AbortStoreError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::Io(error) => Some(error),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for AbortStoreError {
+	fn from(error: io::Error) -> Self {
+		AbortStoreError::Io(error)
+	}
+}
+
+/// Persists the abort sequence to a file so that it survives a power-down.
+///
+/// The file holds the checksum of the script as hexadecimal on its first line, followed by the script
+/// itself. Writes go to a temporary file which is then renamed over the original, so a power loss
+/// mid-write leaves either the old or the new abort sequence in place, never a partial one. Only the
+/// script is persisted, so an abort sequence should not rely on parameters without defaults.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AbortStore {
+	path: PathBuf,
+}
+
+impl AbortStore {
+	/// Constructs a store which keeps the abort sequence at the given path.
+	pub fn new(path: impl Into<PathBuf>) -> Self {
+		AbortStore { path: path.into() }
+	}
+
+	/// The path at which the abort sequence is kept.
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// Atomically writes the abort sequence to disk, replacing any previously saved one.
+	pub fn save(&self, sequence: &Sequence) -> Result<(), AbortStoreError> {
+		let mut temporary_path = self.path.clone().into_os_string();
+		temporary_path.push(".tmp");
+
+		let temporary_path = PathBuf::from(temporary_path);
+
+		let mut file = fs::File::create(&temporary_path)?;
+		write!(file, "{:016x}\n{}", fnv1a(sequence.script.as_bytes()), sequence.script)?;
+		file.sync_all()?;
+		drop(file);
+
+		fs::rename(&temporary_path, &self.path)?;
+
+		// syncing the directory persists the rename itself, but not every platform allows opening a
+		// directory as a file, so this is done on a best-effort basis.
+		if let Some(directory) = self.path.parent().and_then(|parent| fs::File::open(parent).ok()) {
+			let _ = directory.sync_all();
+		}
+
+		Ok(())
+	}
+
+	/// Reads the abort sequence from disk and verifies it against its checksum.
+	pub fn load(&self) -> Result<Sequence, AbortStoreError> {
+		let contents = match fs::read_to_string(&self.path) {
+			Ok(contents) => contents,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => return Err(AbortStoreError::Missing(self.path.clone())),
+			Err(error) => return Err(error.into()),
+		};
+
+		let (checksum, script) = contents
+			.split_once('\n')
+			.ok_or_else(|| AbortStoreError::Malformed(self.path.clone()))?;
+
+		let expected = u64::from_str_radix(checksum, 16)
+			.map_err(|_| AbortStoreError::Malformed(self.path.clone()))?;
+
+		let actual = fnv1a(script.as_bytes());
+
+		if actual != expected {
+			return Err(AbortStoreError::ChecksumMismatch { expected, actual });
+		}
+
+		Ok(Sequence {
+			name: ABORT_NAME.to_owned(),
+			script: script.to_owned(),
+			parameters: Vec::new(),
+			arguments: HashMap::new(),
+		})
+	}
+}
@@ -0,0 +1,339 @@
+use std::{collections::HashMap, sync::{Arc, Mutex, MutexGuard, Weak}, thread, time::{Duration, Instant}};
+
+use jeflog::fail;
+use pyo3::{types::{PyDict, PyModule}, Py, PyAny, PyResult, Python};
+
+use crate::comm::{Sequence, SequenceError, Trigger, TriggerMode};
+use super::{clock, error, handle::StopToken, watchdog::Guard, Namespace, SequenceManager, SequenceRuntime};
+
+#[derive(Debug)]
+struct CompiledTrigger {
+	trigger: Trigger,
+	condition: Py<PyAny>,
+
+	/// Whether the condition was met the last time it was evaluated, used to detect rising edges.
+	was_met: bool,
+
+	/// When the script was last started, used to enforce the cooldown.
+	last_run: Option<Instant>,
+}
+
+/// Every trigger engine currently alive, so that an abort can deactivate the triggers of those in its runtime.
+static ENGINES: Mutex<Vec<Weak<Inner>>> = Mutex::new(Vec::new());
+
+#[derive(Debug)]
+struct Inner {
+	triggers: Mutex<HashMap<String, CompiledTrigger>>,
+	manager: SequenceManager,
+	poll_interval: Mutex<Duration>,
+	poller: Mutex<Option<StopToken>>,
+}
+
+/// Evaluates the conditions of triggers against live device data and runs their scripts when met.
+///
+/// Each condition is compiled once when the trigger is set, then polled at a configurable interval.
+/// Trigger scripts are started as sequences on the given `SequenceManager` under the trigger's name,
+/// so a script which is still running is not started again. Whether a met condition starts the script
+/// is determined by the trigger's mode and cooldown, and when several scripts start on the same poll,
+/// they are started in order of descending priority. Cloning a `TriggerEngine` yields another handle
+/// to the same set of triggers.
+#[derive(Clone, Debug)]
+pub struct TriggerEngine {
+	inner: Arc<Inner>,
+}
+
+impl TriggerEngine {
+	/// Constructs a new engine with no triggers which polls at the given interval once started.
+	pub fn new(manager: SequenceManager, poll_interval: Duration) -> Self {
+		let inner = Arc::new(Inner {
+			triggers: Mutex::new(HashMap::new()),
+			manager,
+			poll_interval: Mutex::new(poll_interval),
+			poller: Mutex::new(None),
+		});
+
+		let mut engines = ENGINES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+		engines.retain(|engine| engine.strong_count() > 0);
+		engines.push(Arc::downgrade(&inner));
+
+		TriggerEngine { inner }
+	}
+
+	fn triggers(&self) -> MutexGuard<'_, HashMap<String, CompiledTrigger>> {
+		self.inner.triggers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	/// Adds a trigger, replacing any existing trigger with the same name.
+	///
+	/// Fails if the condition is not a valid Python expression or is rejected by the sandbox of the
+	/// manager's runtime, in which case any existing trigger with the same name is left in place.
+	pub fn set(&self, trigger: Trigger) -> Result<(), SequenceError> {
+		let sandbox = self.inner.manager.runtime().sandbox();
+
+		let condition = Python::with_gil(|py| {
+			sandbox
+				.map_or(Ok(()), |sandbox| sandbox.check(py, &trigger.condition))
+				.and_then(|_| compile(py, &trigger))
+				.map_err(|error| SequenceError::Exception(error::python_exception(py, &error)))
+		})?;
+
+		self.triggers().insert(trigger.name.clone(), CompiledTrigger {
+			trigger,
+			condition,
+			was_met: false,
+			last_run: None,
+		});
+
+		Ok(())
+	}
+
+	/// Removes the trigger with the given name, returning `false` if there was no such trigger.
+	pub fn remove(&self, name: &str) -> bool {
+		self.triggers().remove(name).is_some()
+	}
+
+	/// Activates the trigger with the given name, returning `false` if there is no such trigger.
+	///
+	/// A condition which is already met upon activation counts as a rising edge.
+	pub fn activate(&self, name: &str) -> bool {
+		self.set_active(name, true)
+	}
+
+	/// Deactivates the trigger with the given name, returning `false` if there is no such trigger.
+	///
+	/// The condition of an inactive trigger is not evaluated.
+	pub fn deactivate(&self, name: &str) -> bool {
+		self.set_active(name, false)
+	}
+
+	fn set_active(&self, name: &str, active: bool) -> bool {
+		let mut triggers = self.triggers();
+
+		let Some(compiled) = triggers.get_mut(name) else {
+			return false;
+		};
+
+		compiled.trigger.active = active;
+		compiled.was_met = false;
+		true
+	}
+
+	/// Gets the trigger with the given name.
+	pub fn get(&self, name: &str) -> Option<Trigger> {
+		self.triggers()
+			.get(name)
+			.map(|compiled| compiled.trigger.clone())
+	}
+
+	/// Gets every trigger, active or not.
+	pub fn all(&self) -> Vec<Trigger> {
+		self.triggers()
+			.values()
+			.map(|compiled| compiled.trigger.clone())
+			.collect()
+	}
+
+	/// Sets the interval at which the conditions of active triggers are polled.
+	pub fn set_poll_interval(&self, poll_interval: Duration) {
+		*self.inner.poll_interval.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = poll_interval;
+	}
+
+	/// Evaluates the condition of every active trigger once, starting the script of each one that
+	/// should run according to its mode and cooldown.
+	///
+	/// Returns the names of the triggers whose scripts were started, in the order they were started.
+	/// Conditions which raise an exception are treated as not met.
+	pub fn poll(&self) -> Vec<String> {
+		let runtime = self.inner.manager.runtime();
+
+		runtime.enter(|| Python::with_gil(|py| {
+			// conditions are evaluated in a fresh namespace on every poll, so no condition can leave
+			// behind state which changes the outcome of another.
+			let namespace = match runtime.prepare(py, Namespace::Isolated) {
+				Ok(namespace) => namespace,
+				Err(error) => {
+					fail!("Failed to prepare namespace for trigger conditions: {error}");
+					return Vec::new();
+				},
+			};
+
+			let watchdog = runtime.watchdog();
+
+			// the armed triggers are copied out so that the lock is not held while their conditions
+			// run, as a condition may call back into this engine, or wait on an abort which must lock
+			// every engine to deactivate its triggers.
+			let armed = self.triggers()
+				.values()
+				.filter(|compiled| compiled.trigger.active)
+				.map(|compiled| (compiled.trigger.name.clone(), compiled.condition.clone_ref(py)))
+				.collect::<Vec<_>>();
+
+			// each outcome is whether the condition was met, or `None` if it overran its budget
+			let outcomes = armed
+				.into_iter()
+				.map(|(name, condition)| {
+					let guard = watchdog.trigger_budget.map(|budget| {
+						Guard::new(py, &name, budget, watchdog.action, None)
+					});
+
+					let result = evaluate(py, &condition, namespace);
+
+					if guard.is_some_and(|guard| guard.finish(py)) {
+						return (name, condition, None);
+					}
+
+					let is_met = result.unwrap_or_else(|error| {
+						fail!("Failed to evaluate condition of trigger '{name}': {error}");
+						false
+					});
+
+					(name, condition, Some(is_met))
+				})
+				.collect::<Vec<_>>();
+
+			let mut triggers = self.triggers();
+			let mut ready = Vec::new();
+
+			for (name, condition, outcome) in outcomes {
+				// the trigger may have been removed, replaced or deactivated while its condition ran
+				let Some(compiled) = triggers
+					.get_mut(&name)
+					.filter(|compiled| compiled.trigger.active && compiled.condition.as_ptr() == condition.as_ptr())
+				else {
+					continue;
+				};
+
+				// a condition interrupted for overrunning its budget would likely overrun again on the
+				// next poll, so its trigger is deactivated until it is reactivated
+				let Some(is_met) = outcome else {
+					fail!(
+						"Condition of trigger '{name}' took longer than its budget of {:?}. Deactivating trigger.",
+						watchdog.trigger_budget.unwrap_or_default(),
+					);
+
+					compiled.trigger.active = false;
+					compiled.was_met = false;
+					continue;
+				};
+
+				let was_met = std::mem::replace(&mut compiled.was_met, is_met);
+
+				let should_run = is_met && match compiled.trigger.mode {
+					TriggerMode::RisingEdge => !was_met,
+					TriggerMode::Level | TriggerMode::OneShot => true,
+				};
+
+				let cooling_down = compiled.trigger.cooldown
+					.zip(compiled.last_run)
+					.is_some_and(|(cooldown, last_run)| clock::now().saturating_duration_since(last_run) < cooldown);
+
+				if should_run && !cooling_down {
+					ready.push((compiled.trigger.priority, name));
+				}
+			}
+
+			ready.sort_by(|(a_priority, a_name), (b_priority, b_name)| {
+				b_priority
+					.cmp(a_priority)
+					.then_with(|| a_name.cmp(b_name))
+			});
+
+			let mut started = Vec::new();
+
+			for (_, name) in ready {
+				let Some(compiled) = triggers.get_mut(&name) else {
+					continue;
+				};
+
+				let sequence = Sequence {
+					name: compiled.trigger.name.clone(),
+					script: compiled.trigger.script.clone(),
+					parameters: Vec::new(),
+					arguments: HashMap::new(),
+				};
+
+				// a script which is still running from a previous firing is left alone
+				if self.inner.manager.start(sequence).is_err() {
+					continue;
+				}
+
+				compiled.last_run = Some(clock::now());
+
+				if compiled.trigger.mode == TriggerMode::OneShot {
+					compiled.trigger.active = false;
+				}
+
+				started.push(compiled.trigger.name.clone());
+			}
+
+			started
+		}))
+	}
+
+	/// Starts polling the conditions of active triggers on a background thread.
+	///
+	/// Does nothing if the engine is already polling.
+	pub fn start(&self) {
+		let mut poller = self.inner.poller.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+		if poller.is_some() {
+			return;
+		}
+
+		let token = StopToken::default();
+		let engine = self.clone();
+
+		*poller = Some(token.clone());
+
+		thread::spawn(move || {
+			while !token.is_stopped() {
+				engine.poll();
+
+				let poll_interval = *engine.inner.poll_interval.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+				token.sleep_until(Instant::now() + poll_interval);
+			}
+		});
+	}
+
+	/// Stops polling on the background thread, if it was started.
+	pub fn stop(&self) {
+		if let Some(token) = self.inner.poller.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+			token.stop();
+		}
+	}
+}
+
+/// Deactivates every trigger of every engine running in the given runtime, as part of its abort.
+pub(crate) fn deactivate_all(runtime: &SequenceRuntime) {
+	let engines = ENGINES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+	for inner in engines.iter().filter_map(Weak::upgrade) {
+		let engine = TriggerEngine { inner };
+
+		if !engine.inner.manager.runtime().ptr_eq(runtime) {
+			continue;
+		}
+
+		for compiled in engine.triggers().values_mut() {
+			compiled.trigger.active = false;
+		}
+	}
+}
+
+/// Compiles the condition of a trigger into a Python code object.
+fn compile(py: Python<'_>, trigger: &Trigger) -> PyResult<Py<PyAny>> {
+	let file_name = format!("<trigger {}>", trigger.name);
+
+	PyModule::import(py, "builtins")?
+		.getattr("compile")?
+		.call1((trigger.condition.as_str(), file_name, "eval"))
+		.map(Into::into)
+}
+
+/// Evaluates a compiled condition within a runtime's namespace, where the sequences library and mappings are defined.
+fn evaluate(py: Python<'_>, condition: &Py<PyAny>, namespace: &PyDict) -> PyResult<bool> {
+	PyModule::import(py, "builtins")?
+		.getattr("eval")?
+		.call1((condition, namespace))?
+		.is_true()
+}
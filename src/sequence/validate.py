@@ -0,0 +1,151 @@
+# Static checks run over sequence scripts before they are uploaded, used by `sequence::validate`.
+#
+# `validate` returns a list of (line, column, severity, message) tuples, where line and column are
+# 1-based and severity is either "error" or "warning".
+
+import ast
+import builtins
+
+# the dimension of each unit constant exported by the sequences module
+UNIT_CONSTANTS = {
+	"A": "current",
+	"mA": "current",
+	"s": "duration",
+	"ms": "duration",
+	"us": "duration",
+	"V": "electric potential",
+	"mV": "electric potential",
+	"lbf": "force",
+	"psi": "pressure",
+	"K": "temperature",
+}
+
+UNITLESS = "unitless number"
+
+# pattern matching was added in Python 3.10, so these node types do not exist on older interpreters
+MATCH_CAPTURES = tuple(getattr(ast, name) for name in ("MatchAs", "MatchStar") if hasattr(ast, name))
+MATCH_MAPPING = tuple(getattr(ast, name) for name in ("MatchMapping",) if hasattr(ast, name))
+
+
+def _assigned_names(tree):
+	names = set()
+
+	for node in ast.walk(tree):
+		if isinstance(node, ast.Name) and isinstance(node.ctx, (ast.Store, ast.Del)):
+			names.add(node.id)
+		elif isinstance(node, (ast.FunctionDef, ast.AsyncFunctionDef, ast.ClassDef)):
+			names.add(node.name)
+		elif isinstance(node, ast.arg):
+			names.add(node.arg)
+		elif isinstance(node, ast.alias):
+			names.add((node.asname or node.name).split(".")[0])
+		elif isinstance(node, ast.ExceptHandler) and node.name:
+			names.add(node.name)
+		elif isinstance(node, (ast.Global, ast.Nonlocal)):
+			names.update(node.names)
+		elif isinstance(node, MATCH_CAPTURES) and node.name:
+			names.add(node.name)
+		elif isinstance(node, MATCH_MAPPING) and node.rest:
+			names.add(node.rest)
+
+	return names
+
+
+def _dimension(node, sensors):
+	"""Infers the physical dimension of an expression, or None if it cannot be determined."""
+
+	if isinstance(node, ast.Constant) and isinstance(node.value, (int, float)) and not isinstance(node.value, bool):
+		return UNITLESS
+
+	if isinstance(node, ast.UnaryOp) and isinstance(node.op, (ast.USub, ast.UAdd)):
+		return _dimension(node.operand, sensors)
+
+	if isinstance(node, ast.Name):
+		if node.id in sensors:
+			return sensors[node.id]
+
+		return UNIT_CONSTANTS.get(node.id)
+
+	# reading a sensor explicitly has the same dimension as comparing against it directly
+	if isinstance(node, ast.Call) and not node.args and isinstance(node.func, ast.Attribute) and node.func.attr == "read":
+		return _dimension(node.func.value, sensors)
+
+	if isinstance(node, ast.BinOp):
+		left = _dimension(node.left, sensors)
+		right = _dimension(node.right, sensors)
+
+		if left is None or right is None:
+			return None
+
+		if isinstance(node.op, ast.Mult):
+			if left == UNITLESS:
+				return right
+
+			if right == UNITLESS:
+				return left
+
+		if isinstance(node.op, ast.Div) and right == UNITLESS:
+			return left
+
+		if isinstance(node.op, (ast.Add, ast.Sub)) and left == right:
+			return left
+
+	return None
+
+
+def _mismatches(tree, sensors):
+	for node in ast.walk(tree):
+		if isinstance(node, ast.Compare):
+			operands = [node.left, *node.comparators]
+			pairs = [
+				(left, right)
+				for op, left, right in zip(node.ops, operands, operands[1:])
+				if isinstance(op, (ast.Lt, ast.LtE, ast.Gt, ast.GtE, ast.Eq, ast.NotEq))
+			]
+			verb = "comparing"
+		elif isinstance(node, ast.BinOp) and isinstance(node.op, (ast.Add, ast.Sub)):
+			pairs = [(node.left, node.right)]
+			verb = "adding" if isinstance(node.op, ast.Add) else "subtracting"
+		else:
+			continue
+
+		for left, right in pairs:
+			left_dimension = _dimension(left, sensors)
+			right_dimension = _dimension(right, sensors)
+
+			if left_dimension is None or right_dimension is None or left_dimension == right_dimension:
+				continue
+
+			# plain numbers may be compared with each other freely
+			if left_dimension == UNITLESS and right_dimension == UNITLESS:
+				continue
+
+			yield node, f"{verb} {left_dimension} with {right_dimension}"
+
+
+def validate(source, filename, sensors, devices, exports):
+	"""
+	Checks a script for names which are never defined and for obviously mismatched units.
+
+	`sensors` maps the name of each mapped sensor or quantity parameter to its dimension, `devices`
+	holds the names of every mapping and parameter, and `exports` holds the names exported by the
+	sequences module.
+	"""
+
+	try:
+		tree = ast.parse(source, filename)
+	except SyntaxError as error:
+		return [(error.lineno or 1, error.offset or 1, "error", f"syntax error: {error.msg}")]
+
+	known = set(dir(builtins)) | set(devices) | set(exports) | _assigned_names(tree)
+	diagnostics = []
+
+	for node in ast.walk(tree):
+		if isinstance(node, ast.Name) and isinstance(node.ctx, ast.Load) and node.id not in known:
+			diagnostics.append((node.lineno, node.col_offset + 1, "error", f"name '{node.id}' is not a mapping, sequences export, or local variable"))
+
+	for node, message in _mismatches(tree, sensors):
+		diagnostics.append((node.lineno, node.col_offset + 1, "warning", message))
+
+	diagnostics.sort()
+	return diagnostics
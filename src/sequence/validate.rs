@@ -0,0 +1,116 @@
+use pyo3::{sync::GILOnceCell, types::{PyDict, PyModule}, Py, PyResult, Python};
+
+use crate::{comm::{Diagnostic, NodeMapping, ParameterKind, SensorType, Sequence, SequenceError, Severity, Unit}, ingest::VirtualSensors};
+use super::error;
+
+/// The Python half of the validator, which walks the syntax tree of a script using the `ast` module.
+const VALIDATOR_SOURCE: &str = include_str!("validate.py");
+
+static VALIDATOR: GILOnceCell<Py<PyModule>> = GILOnceCell::new();
+
+/// Statically checks a sequence against a set of mappings without running it, so that mistakes can be
+/// caught before the sequence is uploaded.
+///
+/// The script is compiled and every name it reads is checked to be either a mapping, an export of the
+/// `sequences` library, a builtin, or a name assigned somewhere in the script. Comparisons, additions
+/// and subtractions between quantities of obviously different units, such as a pressure sensor and a
+/// temperature, are also flagged. Returns the diagnostics found in order of position, which is empty
+/// if the script looks valid.
+///
+/// If the expressions of the virtual sensors in the mappings fail to compile, the units of virtual
+/// sensors are unknown and are not checked, which is reported as a warning at line 0 ahead of the rest.
+pub fn validate(sequence: &Sequence, mappings: &[NodeMapping]) -> Result<Vec<Diagnostic>, SequenceError> {
+	super::initialize_python();
+
+	let (virtual_sensors, mapping_diagnostic) = match VirtualSensors::compile(mappings) {
+		Ok(virtual_sensors) => (virtual_sensors, None),
+		Err(error) => {
+			let diagnostic = Diagnostic {
+				line: 0,
+				column: 0,
+				severity: Severity::Warning,
+				message: format!("units of virtual sensors are not checked because their mappings are invalid: {error}"),
+			};
+
+			(VirtualSensors::default(), Some(diagnostic))
+		},
+	};
+
+	Python::with_gil(|py| {
+		let exports = PyModule::import(py, "sequences")
+			.and_then(|module| module.dir().extract::<Vec<String>>())
+			.map_err(|error| SequenceError::Import(error::python_exception(py, &error)))?;
+
+		let sensors = PyDict::new(py);
+		let mut devices = Vec::with_capacity(mappings.len());
+
+		for mapping in mappings {
+			let unit = match mapping.sensor_type {
+				SensorType::Valve => None,
+				SensorType::Virtual => virtual_sensors.unit(&mapping.text_id),
+				sensor_type => Some(sensor_type.channel_types()[0].unit()),
+			};
+
+			if let Some(unit) = unit {
+				sensors
+					.set_item(&mapping.text_id, dimension(unit))
+					.map_err(|error| SequenceError::Exception(error::python_exception(py, &error)))?;
+			}
+
+			devices.push(mapping.text_id.as_str());
+		}
+
+		// parameters are defined as globals just like mappings, and quantities have a dimension
+		for parameter in &sequence.parameters {
+			if let ParameterKind::Quantity(unit) = parameter.kind {
+				sensors
+					.set_item(&parameter.name, dimension(unit))
+					.map_err(|error| SequenceError::Exception(error::python_exception(py, &error)))?;
+			}
+
+			devices.push(parameter.name.as_str());
+		}
+
+		let file_name = format!("<sequence {}>", sequence.name);
+
+		let diagnostics = validator(py)
+			.and_then(|validator| {
+				validator
+					.as_ref(py)
+					.getattr("validate")?
+					.call1((sequence.script.as_str(), file_name, sensors, devices, exports))?
+					.extract::<Vec<(u32, u32, String, String)>>()
+			})
+			.map_err(|error| SequenceError::Exception(error::python_exception(py, &error)))?;
+
+		let diagnostics = diagnostics
+			.into_iter()
+			.map(|(line, column, severity, message)| Diagnostic {
+				line,
+				column,
+				severity: if severity == "error" { Severity::Error } else { Severity::Warning },
+				message,
+			});
+
+		Ok(mapping_diagnostic.into_iter().chain(diagnostics).collect())
+	})
+}
+
+/// Loads the Python half of the validator, once per process.
+fn validator(py: Python<'_>) -> PyResult<&Py<PyModule>> {
+	VALIDATOR.get_or_try_init(py, || {
+		PyModule::from_code(py, VALIDATOR_SOURCE, "validate.py", "_sequences_validate").map(Into::into)
+	})
+}
+
+/// The name of the physical dimension measured in a unit, matching the names used by the validator
+/// for the unit constants of the `sequences` library.
+fn dimension(unit: Unit) -> &'static str {
+	match unit {
+		Unit::Amps => "current",
+		Unit::Psi => "pressure",
+		Unit::Kelvin => "temperature",
+		Unit::Pounds => "force",
+		Unit::Volts => "electric potential",
+	}
+}
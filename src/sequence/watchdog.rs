@@ -0,0 +1,264 @@
+use std::{cell::RefCell, os::raw::c_long, sync::{Arc, Mutex, MutexGuard, Weak}, thread::{self, Thread}, time::{Duration, Instant}};
+
+use jeflog::warn;
+use pyo3::{ffi, AsPyPointer, PyTypeInfo, Python};
+
+use super::{handle::StopToken, AbortError, SequenceRuntime, StopError};
+
+/// How often the watchdog checks running sequences and trigger evaluations against their budgets.
+const WATCHDOG_POLL: Duration = Duration::from_millis(5);
+
+/// The budgets of every running sequence and trigger evaluation, checked by the watchdog thread.
+static ENTRIES: Mutex<Vec<Weak<Entry>>> = Mutex::new(Vec::new());
+
+/// The watchdog thread, spawned when the first budget is enforced.
+static WATCHDOG: Mutex<Option<Thread>> = Mutex::new(None);
+
+thread_local! {
+	/// The budget of the sequence or trigger evaluation running on the current thread, if any.
+	static CURRENT_ENTRY: RefCell<Option<Arc<Entry>>> = const { RefCell::new(None) };
+}
+
+/// What the watchdog does when a sequence or trigger evaluation overruns its budget.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OverrunAction {
+	/// Logs a warning and lets it continue.
+	Warn,
+
+	/// Interrupts it, failing the sequence or deactivating the trigger.
+	#[default]
+	Stop,
+
+	/// Interrupts it and aborts the vehicle, as if `abort()` had been called.
+	Abort,
+}
+
+/// Time budgets enforced on the sequences and trigger conditions run by a runtime.
+///
+/// A sequence's budget limits how long it may run without waiting, such as by `wait_for`, so that a
+/// busy loop cannot starve every other sequence and trigger of the GIL. Time spent waiting does not
+/// count against the budget, and budgets are measured in real time regardless of the sequence clock.
+/// A sequence interrupted by the watchdog fails with `SequenceError::Overrun`, unless it finishes before
+/// the interruption reaches it.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Watchdog {
+	/// The longest a sequence may run between waits, or `None` for no limit.
+	pub sequence_budget: Option<Duration>,
+
+	/// The longest a single evaluation of a trigger condition may take, or `None` for no limit.
+	pub trigger_budget: Option<Duration>,
+
+	/// What to do when either budget is overrun.
+	pub action: OverrunAction,
+}
+
+#[derive(Debug)]
+struct EntryState {
+	/// When the guarded code last started running, or `None` while it is waiting.
+	running_since: Option<Instant>,
+
+	/// Whether the budget has been overrun since the guarded code last waited.
+	overrun: bool,
+
+	/// Whether the guarded code has finished, after which it must never be interrupted.
+	finished: bool,
+}
+
+#[derive(Debug)]
+struct Entry {
+	name: String,
+	thread: c_long,
+	budget: Duration,
+	action: OverrunAction,
+	token: Option<StopToken>,
+	runtime: Option<SequenceRuntime>,
+	state: Mutex<EntryState>,
+}
+
+impl Entry {
+	fn state(&self) -> MutexGuard<'_, EntryState> {
+		self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+}
+
+/// Enforces a budget on the code run on the calling thread while it is held.
+#[derive(Debug)]
+pub(crate) struct Guard {
+	entry: Arc<Entry>,
+	previous: Option<Arc<Entry>>,
+}
+
+impl Guard {
+	/// Starts enforcing a budget on the calling thread, which must hold the GIL. The token, if any, is
+	/// also signalled to stop upon an overrun, in case the guarded code is not running Python code.
+	pub(crate) fn new(py: Python<'_>, name: &str, budget: Duration, action: OverrunAction, token: Option<StopToken>) -> Self {
+		// Python identifies threads by their native identifier, which fits in a C long on every
+		// platform it supports.
+		let thread = py.import("threading")
+			.and_then(|threading| threading.call_method0("get_ident"))
+			.and_then(|ident| ident.extract::<u64>())
+			.unwrap_or_default() as c_long;
+
+		let entry = Arc::new(Entry {
+			name: name.to_owned(),
+			thread,
+			budget,
+			action,
+			token,
+			runtime: SequenceRuntime::current(),
+			state: Mutex::new(EntryState {
+				running_since: Some(Instant::now()),
+				overrun: false,
+				finished: false,
+			}),
+		});
+
+		ENTRIES
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+			.push(Arc::downgrade(&entry));
+
+		start_watchdog();
+
+		let previous = CURRENT_ENTRY.replace(Some(entry.clone()));
+		Guard { entry, previous }
+	}
+
+	/// Stops enforcing the budget, returning whether the guarded code was to be interrupted for
+	/// overrunning it.
+	///
+	/// Must be called with the GIL held and immediately after the guarded code returns, so that an
+	/// interruption which was requested but not yet delivered is cancelled before any other Python code
+	/// runs on this thread. The guarded code may therefore have completed despite being interrupted.
+	pub(crate) fn finish(self, _py: Python<'_>) -> bool {
+		let mut state = self.entry.state();
+		state.finished = true;
+
+		let interrupted = state.overrun && self.entry.action != OverrunAction::Warn;
+
+		if interrupted {
+			// SAFETY: the GIL is held, and a null exception clears any pending asynchronous exception.
+			unsafe { ffi::PyThreadState_SetAsyncExc(self.entry.thread, std::ptr::null_mut()) };
+		}
+
+		drop(state);
+		CURRENT_ENTRY.set(self.previous);
+
+		interrupted
+	}
+}
+
+/// Pauses the budget of the code running on the calling thread while it waits, which must hold the GIL.
+pub(crate) fn pause() {
+	CURRENT_ENTRY.with_borrow(|entry| {
+		if let Some(entry) = entry {
+			entry.state().running_since = None;
+		}
+	});
+}
+
+/// Resumes the budget of the code running on the calling thread after it has waited, which must hold the GIL.
+pub(crate) fn resume() {
+	CURRENT_ENTRY.with_borrow(|entry| {
+		if let Some(entry) = entry {
+			let mut state = entry.state();
+			state.running_since = Some(Instant::now());
+
+			// an overrun which only warned is reported again if the next stretch overruns too
+			if entry.action == OverrunAction::Warn {
+				state.overrun = false;
+			}
+		}
+	});
+}
+
+/// Spawns the watchdog thread if it is not running, or wakes it if it is idle.
+fn start_watchdog() {
+	let mut watchdog = WATCHDOG.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+	match &*watchdog {
+		Some(thread) => thread.unpark(),
+		None => *watchdog = Some(thread::spawn(watch).thread().clone()),
+	}
+}
+
+/// Checks every budget periodically, parking while there are none to check.
+fn watch() {
+	loop {
+		let entries = {
+			let mut entries = ENTRIES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+			entries.retain(|entry| entry.strong_count() > 0);
+			entries.iter().filter_map(Weak::upgrade).collect::<Vec<_>>()
+		};
+
+		if entries.is_empty() {
+			thread::park();
+			continue;
+		}
+
+		for entry in entries {
+			check(&entry);
+		}
+
+		thread::sleep(WATCHDOG_POLL);
+	}
+}
+
+/// Acts on an entry which has overrun its budget.
+///
+/// An entry which is to be interrupted is interrupted again on every check until it finishes, so that
+/// code catching every exception cannot outlast the watchdog.
+fn check(entry: &Entry) {
+	let first = {
+		let mut state = entry.state();
+
+		if state.finished {
+			return;
+		} else if state.overrun {
+			false
+		} else if state.running_since.is_some_and(|since| since.elapsed() > entry.budget) {
+			state.overrun = true;
+			true
+		} else {
+			return;
+		}
+	};
+
+	if entry.action == OverrunAction::Warn {
+		if first {
+			warn!("'{}' overran its budget of {:?}.", entry.name, entry.budget);
+		}
+
+		return;
+	}
+
+	if let Some(token) = entry.token.as_ref().filter(|_| first) {
+		token.stop();
+	}
+
+	Python::with_gil(|py| {
+		// the entry may have finished while the GIL was being acquired, in which case its thread may
+		// already be running unrelated code which must not be interrupted.
+		if entry.state().finished {
+			return;
+		}
+
+		let exception = match entry.action {
+			OverrunAction::Abort => AbortError::type_object(py),
+			_ => StopError::type_object(py),
+		};
+
+		// SAFETY: the GIL is held and the exception is a valid type object, which is only borrowed.
+		unsafe { ffi::PyThreadState_SetAsyncExc(entry.thread, exception.as_ptr()) };
+	});
+
+	if first && entry.action == OverrunAction::Abort {
+		warn!("'{}' overran its budget of {:?}. Aborting.", entry.name, entry.budget);
+
+		// the abort is only run after interrupting the entry, which may hold locks the abort needs
+		match &entry.runtime {
+			Some(runtime) => runtime.abort(),
+			None => super::run_abort(),
+		};
+	}
+}
@@ -0,0 +1,132 @@
+//! Aborts reach every sequence in the default runtime, so these tests live in their own test binary.
+
+mod support;
+
+use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}, time::{Duration, Instant}};
+
+use common::{
+	comm::{CompositeValveState, Measurement, SequenceError, SequenceStatus, TriggerMode, ValveState},
+	sequence::{self, AbortStore, AbortStoreError, DeviceError, DeviceHandler, SequenceManager, SequenceRuntime, TriggerEngine},
+};
+use support::{aborts, actuations, add_valve, sequence, set_reading, trigger};
+
+#[test]
+fn abort_unwinds_sequences_and_runs_abort_sequence_once() {
+	add_valve("ABORT_V");
+	set_reading("ABORT_PT", 80.0);
+
+	// calling abort() from within the abort sequence must not interrupt it
+	sequence::set_abort_sequence(sequence("abort", "ABORT_V.close()\nwait_for(200 * ms)\nabort()")).unwrap();
+
+	let manager = SequenceManager::new();
+	let engine = TriggerEngine::new(manager.clone(), Duration::from_millis(10));
+	engine.set(trigger("abort_trigger", "ABORT_PT < 90 * psi", TriggerMode::Level)).unwrap();
+
+	manager.start(sequence("forever", "while True:\n\twait_for(1 * s)")).unwrap();
+	manager.start(sequence("swallower", "try:\n\twait_for(10 * s)\nexcept Exception:\n\twait_for(10 * s)")).unwrap();
+	manager.start(sequence("caller", "wait_for(50 * ms)\nabort()\nwait_for(10 * s)")).unwrap();
+
+	let aborted = Some(SequenceStatus::Failed(SequenceError::Aborted));
+	assert_eq!(manager.wait("caller"), aborted);
+	assert_eq!(manager.wait("forever"), aborted);
+	assert_eq!(manager.wait("swallower"), aborted);
+	assert!(!engine.get("abort_trigger").unwrap().active);
+	assert_eq!(aborts(), 1);
+
+	// the abort sequence is still running, so a second abort is ignored
+	assert!(sequence::run_abort().is_none());
+
+	// once the abort sequence has finished, another abort runs it again, and it cannot be stopped
+	let started = Instant::now();
+
+	let handle = loop {
+		if let Some(handle) = sequence::run_abort() {
+			break handle;
+		}
+
+		assert!(started.elapsed() < Duration::from_secs(5), "abort sequence never finished");
+		std::thread::sleep(Duration::from_millis(1));
+	};
+
+	handle.stop();
+
+	assert_eq!(handle.join(), Ok(()));
+	assert_eq!(actuations("ABORT_V"), [ValveState::Closed, ValveState::Closed]);
+	assert_eq!(aborts(), 2);
+}
+
+/// Counts the aborts it is told of, and has no devices.
+struct AbortCounter(Arc<AtomicUsize>);
+
+impl DeviceHandler for AbortCounter {
+	fn read_sensor(&self, name: &str) -> Result<Measurement, DeviceError> {
+		Err(DeviceError::UnknownDevice(name.to_owned()))
+	}
+
+	fn read_valve_state(&self, name: &str) -> Result<CompositeValveState, DeviceError> {
+		Err(DeviceError::UnknownDevice(name.to_owned()))
+	}
+
+	fn actuate_valve(&self, name: &str, _state: ValveState) -> Result<(), DeviceError> {
+		Err(DeviceError::UnknownDevice(name.to_owned()))
+	}
+
+	fn abort(&self) {
+		self.0.fetch_add(1, Ordering::SeqCst);
+	}
+}
+
+#[test]
+fn abort_is_scoped_to_its_runtime() {
+	let runtimes = [(); 2].map(|_| {
+		let aborts = Arc::new(AtomicUsize::new(0));
+		let runtime = SequenceRuntime::new(Arc::new(Mutex::new(Vec::new())));
+		runtime.set_device_handler(AbortCounter(aborts.clone()));
+
+		let manager = SequenceManager::with_runtime(runtime.clone());
+		let engine = TriggerEngine::new(manager.clone(), Duration::from_millis(10));
+		engine.set(trigger("scoped_trigger", "False", TriggerMode::Level)).unwrap();
+
+		manager.start(sequence("scoped_forever", "while True:\n\twait_for(1 * s)")).unwrap();
+		(aborts, manager, engine)
+	});
+
+	let [(aborted_count, aborted, aborted_engine), (other_count, other, other_engine)] = runtimes;
+
+	aborted.start(sequence("scoped_caller", "abort()")).unwrap();
+
+	let aborted_status = Some(SequenceStatus::Failed(SequenceError::Aborted));
+	assert_eq!(aborted.wait("scoped_caller"), aborted_status);
+	assert_eq!(aborted.wait("scoped_forever"), aborted_status);
+	assert!(!aborted_engine.get("scoped_trigger").unwrap().active);
+	assert_eq!(aborted_count.load(Ordering::SeqCst), 1);
+
+	// the other runtime is untouched
+	assert_eq!(other.status("scoped_forever"), Some(SequenceStatus::Running));
+	assert!(other_engine.get("scoped_trigger").unwrap().active);
+	assert_eq!(other_count.load(Ordering::SeqCst), 0);
+
+	other.stop("scoped_forever");
+	assert_eq!(other.wait("scoped_forever"), Some(SequenceStatus::Stopped));
+}
+
+#[test]
+fn abort_store_round_trips_and_detects_corruption() {
+	let path = std::env::temp_dir().join(format!("abort-store-test-{}", std::process::id()));
+	let store = AbortStore::new(&path);
+
+	assert!(matches!(store.load(), Err(AbortStoreError::Missing(_))));
+
+	let abort = sequence("abort", "BBV.close()\nSWV.close()\n");
+	store.save(&abort).unwrap();
+	assert_eq!(store.load().unwrap(), abort);
+
+	let corrupted = std::fs::read_to_string(&path).unwrap().replace("SWV", "SVW");
+	std::fs::write(&path, corrupted).unwrap();
+	assert!(matches!(store.load(), Err(AbortStoreError::ChecksumMismatch { .. })));
+
+	std::fs::write(&path, "not a checksum").unwrap();
+	assert!(matches!(store.load(), Err(AbortStoreError::Malformed(_))));
+
+	std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,106 @@
+//! The sequence clock is shared by the whole process, so these tests live in their own test binary.
+
+mod support;
+
+use std::{sync::{Arc, Mutex, Once}, time::{Duration, Instant}};
+
+use common::{
+	comm::{SensorType, SequenceError, Unit, ValveState},
+	sequence::{self, SensorSource, SimulatedClock, SimulatedSensor, SimulatedTank, SimulatedValve, Simulation},
+};
+use support::{await_sleeping, mapping, sequence};
+
+/// Held by each test for its duration, since each one installs its own clock.
+static CLOCK_LOCK: Mutex<()> = Mutex::new(());
+
+fn initialize() {
+	static INITIALIZE: Once = Once::new();
+
+	INITIALIZE.call_once(|| {
+		sequence::initialize(Arc::new(Mutex::new(vec![
+			mapping("FILL_PT", SensorType::Pt),
+			mapping("FILL_V", SensorType::Valve),
+		])));
+	});
+}
+
+fn fill_simulation(clock: &SimulatedClock) -> Simulation {
+	let simulation = Simulation::with_clock(39, clock.clone());
+
+	simulation.add_tank("tank", SimulatedTank {
+		pressure: 100.0,
+		max_pressure: 500.0,
+		leak_rate: 0.0,
+		flows: vec![("FILL_V".to_owned(), 0.5)],
+	});
+
+	simulation.add_sensor("FILL_PT", SimulatedSensor {
+		source: SensorSource::Tank("tank".to_owned()),
+		unit: Unit::Psi,
+		noise: 0.0,
+	});
+
+	simulation.add_valve("FILL_V", SimulatedValve {
+		initial: ValveState::Closed,
+		actuation_delay: Duration::from_millis(100),
+	});
+
+	simulation.install();
+	simulation
+}
+
+#[test]
+fn auto_advancing_clock_runs_long_sequences_instantly() {
+	let _lock = CLOCK_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+	initialize();
+
+	let clock = SimulatedClock::auto_advancing();
+	sequence::set_clock(clock.clone());
+
+	let simulation = fill_simulation(&clock);
+
+	let script = "\
+FILL_V.open()
+wait_until(lambda: FILL_PT > 450 * psi, 15 * 60 * s, 1 * s)
+FILL_V.close()
+for _ in interval(3, 60 * s):
+	pass";
+
+	let start = Instant::now();
+	sequence::run(sequence("fill", script)).unwrap();
+
+	// filling 350 psi at 0.5 psi/s takes just over 700 simulated seconds, then the interval spans two more minutes
+	assert!(start.elapsed() < Duration::from_secs(10));
+	assert!((820..=830).contains(&clock.elapsed().as_secs()), "clock advanced {:?}", clock.elapsed());
+	assert!(simulation.pressure("tank").unwrap() > 450.0);
+	assert_eq!(simulation.actual("FILL_V"), Some(ValveState::Closed));
+}
+
+#[test]
+fn stepped_clock_controls_waits() {
+	let _lock = CLOCK_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+	initialize();
+
+	let clock = SimulatedClock::new();
+	sequence::set_clock(clock.clone());
+
+	let simulation = fill_simulation(&clock);
+	let handle = sequence::start(sequence("delayed", "wait_for(10 * 60 * s)\nFILL_V.open()"));
+	await_sleeping(&clock, 1);
+
+	// the sequence goes back to sleep once it has seen the step, as its deadline is a minute away
+	clock.advance(Duration::from_secs(9 * 60));
+	await_sleeping(&clock, 1);
+	assert_eq!(simulation.commanded("FILL_V"), Some(ValveState::Closed));
+
+	clock.advance(Duration::from_secs(60));
+	handle.join().unwrap();
+	assert_eq!(simulation.commanded("FILL_V"), Some(ValveState::Open));
+
+	// a sleep on a stepped clock is still interrupted by stopping its sequence
+	let handle = sequence::start(sequence("stopped", "wait_for(1 * s)"));
+	await_sleeping(&clock, 1);
+
+	handle.stop();
+	assert_eq!(handle.join(), Err(SequenceError::Stopped));
+}
@@ -0,0 +1,150 @@
+use common::comm::{Computer, MappingFingerprint, NodeMapping, Parameter, ParameterKind, ParameterValue, SensorType, Sequence, Trigger, TriggerMode};
+use std::collections::HashMap;
+use serde::Serialize;
+
+fn mapping(text_id: &str, channel: u32) -> NodeMapping {
+	NodeMapping {
+		text_id: text_id.to_owned(),
+		board_id: "sam-01".to_owned(),
+		sensor_type: SensorType::Pt,
+		channel,
+		computer: Computer::Flight,
+		max: Some(1000.0),
+		min: Some(0.0),
+		calibrated_offset: 0.0,
+		powered_threshold: None,
+		normally_closed: None,
+		expression: None,
+		filter: None,
+	}
+}
+
+#[test]
+fn fingerprint_is_order_independent() {
+	let forward = vec![mapping("KTPT", 1), mapping("WTPT", 2), mapping("FUPT", 3)];
+	let backward = forward.iter().rev().cloned().collect::<Vec<_>>();
+
+	assert_eq!(MappingFingerprint::of(&forward), MappingFingerprint::of(&backward));
+}
+
+#[test]
+fn fingerprint_detects_drift() {
+	let original = vec![mapping("KTPT", 1), mapping("WTPT", 2)];
+
+	let mut moved = original.clone();
+	moved[1].channel = 3;
+
+	let mut duplicated = original.clone();
+	duplicated.push(original[0].clone());
+
+	let fingerprint = MappingFingerprint::of(&original);
+	assert_ne!(fingerprint, MappingFingerprint::of(&moved));
+	assert_ne!(fingerprint, MappingFingerprint::of(&duplicated));
+	assert_ne!(fingerprint, MappingFingerprint::of(&[]));
+}
+
+/// The layout of `NodeMapping` before virtual sensors and filters were added.
+#[derive(Serialize)]
+struct LegacyNodeMapping {
+	text_id: String,
+	board_id: String,
+	sensor_type: SensorType,
+	channel: u32,
+	computer: Computer,
+	max: Option<f64>,
+	min: Option<f64>,
+	calibrated_offset: f64,
+	powered_threshold: Option<f64>,
+	normally_closed: Option<bool>,
+}
+
+#[test]
+fn postcard_mappings_without_expression_and_filter_are_rejected() {
+	let legacy = LegacyNodeMapping {
+		text_id: "KTPT".to_owned(),
+		board_id: "sam-01".to_owned(),
+		sensor_type: SensorType::Pt,
+		channel: 1,
+		computer: Computer::Flight,
+		max: Some(1000.0),
+		min: Some(0.0),
+		calibrated_offset: 0.0,
+		powered_threshold: None,
+		normally_closed: None,
+	};
+
+	// serde defaults do not apply to postcard, so the missing trailing fields are an error
+	let encoded = postcard::to_allocvec(&legacy).unwrap();
+	assert!(postcard::from_bytes::<NodeMapping>(&encoded).is_err());
+
+	let current = mapping("KTPT", 1);
+	let encoded = postcard::to_allocvec(&current).unwrap();
+	assert_eq!(postcard::from_bytes::<NodeMapping>(&encoded).unwrap(), current);
+}
+
+/// The layout of `Trigger` before modes, cooldowns and priorities were added.
+#[derive(Serialize)]
+struct LegacyTrigger {
+	name: String,
+	condition: String,
+	script: String,
+	active: bool,
+}
+
+#[test]
+fn postcard_triggers_without_mode_cooldown_and_priority_are_rejected() {
+	let legacy = LegacyTrigger {
+		name: "low_ktpt".to_owned(),
+		condition: "KTPT < 90 * psi".to_owned(),
+		script: "abort()".to_owned(),
+		active: true,
+	};
+
+	let encoded = postcard::to_allocvec(&legacy).unwrap();
+	assert!(postcard::from_bytes::<Trigger>(&encoded).is_err());
+
+	let current = Trigger {
+		name: legacy.name,
+		condition: legacy.condition,
+		script: legacy.script,
+		active: legacy.active,
+		mode: TriggerMode::Level,
+		cooldown: Some(std::time::Duration::from_secs(5)),
+		priority: 2,
+	};
+
+	let encoded = postcard::to_allocvec(&current).unwrap();
+	assert_eq!(postcard::from_bytes::<Trigger>(&encoded).unwrap(), current);
+}
+
+/// The layout of `Sequence` before parameters were added.
+#[derive(Serialize)]
+struct LegacySequence {
+	name: String,
+	script: String,
+}
+
+#[test]
+fn postcard_sequences_without_parameters_and_arguments_are_rejected() {
+	let legacy = LegacySequence {
+		name: "fill".to_owned(),
+		script: "BBV.open()".to_owned(),
+	};
+
+	let encoded = postcard::to_allocvec(&legacy).unwrap();
+	assert!(postcard::from_bytes::<Sequence>(&encoded).is_err());
+
+	let current = Sequence {
+		name: legacy.name,
+		script: legacy.script,
+		parameters: vec![Parameter {
+			name: "cycles".to_owned(),
+			kind: ParameterKind::Integer,
+			default: Some(ParameterValue::Integer(1)),
+		}],
+		arguments: HashMap::from([("cycles".to_owned(), ParameterValue::Integer(3))]),
+	};
+
+	let encoded = postcard::to_allocvec(&current).unwrap();
+	assert_eq!(postcard::from_bytes::<Sequence>(&encoded).unwrap(), current);
+}
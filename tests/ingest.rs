@@ -0,0 +1,291 @@
+use common::{
+	comm::{ChannelType, Computer, DataMessage, DataPoint, FilterConfig, Measurement, NodeMapping, RailStatistics, SensorType, Unit, VehicleState},
+	ingest::{BoardHealthMonitor, Expression, ExpressionError, FilterBank, FilterError, HealthLimits, VirtualSensors},
+};
+use std::borrow::Cow;
+
+fn mapping(text_id: &str, sensor_type: SensorType, expression: Option<&str>) -> NodeMapping {
+	NodeMapping {
+		text_id: text_id.to_owned(),
+		board_id: "sam-01".to_owned(),
+		sensor_type,
+		channel: 0,
+		computer: Computer::Flight,
+		max: None,
+		min: None,
+		calibrated_offset: 0.0,
+		powered_threshold: None,
+		normally_closed: None,
+		expression: expression.map(str::to_owned),
+		filter: None,
+	}
+}
+
+#[test]
+fn virtual_sensors_evaluate_in_dependency_order() -> anyhow::Result<()> {
+	let mappings = vec![
+		mapping("HALF_DP", SensorType::Virtual, Some("INJ_DP / 2")),
+		mapping("INJ_DP", SensorType::Virtual, Some("FUPT - CCPT")),
+		mapping("FUPT", SensorType::Pt, None),
+		mapping("CCPT", SensorType::Pt, None),
+	];
+
+	let virtual_sensors = VirtualSensors::compile(&mappings)?;
+
+	let mut state = VehicleState::new();
+	state.sensor_readings.insert("FUPT".to_owned(), Measurement { value: 500.0, unit: Unit::Psi });
+	state.sensor_readings.insert("CCPT".to_owned(), Measurement { value: 320.0, unit: Unit::Psi });
+	virtual_sensors.evaluate(&mut state);
+
+	assert_eq!(state.sensor_readings["INJ_DP"], Measurement { value: 180.0, unit: Unit::Psi });
+	assert_eq!(state.sensor_readings["HALF_DP"], Measurement { value: 90.0, unit: Unit::Psi });
+
+	Ok(())
+}
+
+#[test]
+fn virtual_sensors_reject_bad_expressions() {
+	let mismatched = vec![
+		mapping("BAD", SensorType::Virtual, Some("FUPT + LC1")),
+		mapping("FUPT", SensorType::Pt, None),
+		mapping("LC1", SensorType::LoadCell, None),
+	];
+
+	assert!(matches!(VirtualSensors::compile(&mismatched), Err(ExpressionError::UnitMismatch { .. })));
+
+	let cyclic = vec![
+		mapping("A", SensorType::Virtual, Some("B * 2")),
+		mapping("B", SensorType::Virtual, Some("A * 2")),
+	];
+
+	assert!(matches!(VirtualSensors::compile(&cyclic), Err(ExpressionError::Cycle(_))));
+
+	let unbalanced = vec![mapping("A", SensorType::Virtual, Some("(LC1 + LC2"))];
+	assert!(matches!(VirtualSensors::compile(&unbalanced), Err(ExpressionError::Syntax(_))));
+}
+
+#[test]
+fn expressions_parse_exponents_and_limit_nesting() {
+	assert_eq!(Expression::parse("1e3"), Ok(Expression::Constant(1000.0)));
+	assert_eq!(Expression::parse("2.5E-4"), Ok(Expression::Constant(2.5e-4)));
+	assert_eq!(Expression::parse(".5e+1"), Ok(Expression::Constant(5.0)));
+
+	let syntax_error = |source: &str| match Expression::parse(source) {
+		Err(ExpressionError::Syntax(reason)) => reason,
+		result => panic!("expected '{source}' to be rejected, got {result:?}"),
+	};
+
+	assert!(syntax_error("1.2.3").contains("more than one decimal point"));
+	assert!(syntax_error("1e").contains("no digits"));
+	assert!(syntax_error("1e-").contains("no digits"));
+
+	// deeply nested expressions are rejected instead of overflowing the stack
+	let nested = |depth: usize, prefix: &str, suffix: &str| format!("{}FUPT{}", prefix.repeat(depth), suffix.repeat(depth));
+
+	assert!(Expression::parse(&nested(60, "(", ")")).is_ok());
+	assert!(Expression::parse(&nested(60, "-", "")).is_ok());
+	assert!(syntax_error(&nested(10_000, "(", ")")).contains("nested"));
+	assert!(syntax_error(&nested(10_000, "-", "")).contains("nested"));
+	assert!(syntax_error(&nested(10_000, "", " + 1")).contains("nested"));
+}
+
+#[test]
+fn virtual_sensors_require_a_unit_and_a_finite_result() -> anyhow::Result<()> {
+	// a ratio of like quantities has no unit, so it cannot be a virtual sensor on its own
+	let ratio = vec![
+		mapping("RATIO", SensorType::Virtual, Some("FUPT / CCPT")),
+		mapping("FUPT", SensorType::Pt, None),
+		mapping("CCPT", SensorType::Pt, None),
+	];
+
+	let Err(ExpressionError::UnitMismatch { reason, .. }) = VirtualSensors::compile(&ratio) else {
+		panic!("expected a unitless result to be rejected");
+	};
+
+	assert!(reason.contains("no unit"));
+
+	// but it may scale a quantity which does have a unit
+	let scaled = vec![
+		mapping("SCALED", SensorType::Virtual, Some("FUPT / CCPT * FUPT")),
+		mapping("FUPT", SensorType::Pt, None),
+		mapping("CCPT", SensorType::Pt, None),
+	];
+
+	let virtual_sensors = VirtualSensors::compile(&scaled)?;
+	let mut state = VehicleState::new();
+
+	state.sensor_readings.insert("FUPT".to_owned(), Measurement { value: 400.0, unit: Unit::Psi });
+	state.sensor_readings.insert("CCPT".to_owned(), Measurement { value: 200.0, unit: Unit::Psi });
+	virtual_sensors.evaluate(&mut state);
+
+	assert_eq!(state.sensor_readings["SCALED"], Measurement { value: 800.0, unit: Unit::Psi });
+
+	// dividing by a reading of zero leaves the last finite value in place
+	state.sensor_readings.insert("CCPT".to_owned(), Measurement { value: 0.0, unit: Unit::Psi });
+	virtual_sensors.evaluate(&mut state);
+
+	assert_eq!(state.sensor_readings["SCALED"], Measurement { value: 800.0, unit: Unit::Psi });
+
+	state.sensor_readings.insert("FUPT".to_owned(), Measurement { value: 0.0, unit: Unit::Psi });
+	virtual_sensors.evaluate(&mut state);
+
+	assert_eq!(state.sensor_readings["SCALED"], Measurement { value: 800.0, unit: Unit::Psi });
+
+	Ok(())
+}
+
+#[test]
+fn filter_bank_keeps_raw_and_filtered_readings() {
+	let mut pt = mapping("WTPT", SensorType::Pt, None);
+	pt.filter = Some(FilterConfig::Median { window: 3 });
+
+	let mut lc = mapping("LC1", SensorType::LoadCell, None);
+	lc.filter = Some(FilterConfig::MovingAverage { window: 2 });
+
+	let mut bank = FilterBank::new(&[pt, lc, mapping("KTPT", SensorType::Pt, None)]).unwrap();
+	let mut state = VehicleState::new();
+
+	for (i, value) in [200.0, 205.0, 900.0, 202.0].into_iter().enumerate() {
+		bank.ingest(&mut state, "WTPT", Measurement { value, unit: Unit::Psi }, i as f64);
+	}
+
+	assert_eq!(state.sensor_readings["WTPT"].value, 205.0);
+	assert_eq!(state.raw_sensor_readings["WTPT"].value, 202.0);
+
+	assert_eq!(bank.apply("LC1", 10.0, 0.0), 10.0);
+	assert_eq!(bank.apply("LC1", 20.0, 1.0), 15.0);
+	assert_eq!(bank.apply("LC1", 40.0, 2.0), 30.0);
+
+	bank.ingest(&mut state, "KTPT", Measurement { value: 80.0, unit: Unit::Psi }, 0.0);
+	assert_eq!(state.sensor_readings["KTPT"].value, 80.0);
+	assert!(!state.raw_sensor_readings.contains_key("KTPT"));
+}
+
+#[test]
+fn low_pass_filter_attenuates_steps() {
+	let mut pt = mapping("WTPT", SensorType::Pt, None);
+	pt.filter = Some(FilterConfig::LowPass { cutoff: 1.0 });
+
+	let mut bank = FilterBank::new(&[pt]).unwrap();
+	assert_eq!(bank.apply("WTPT", 0.0, 0.0), 0.0);
+
+	let stepped = bank.apply("WTPT", 100.0, 0.01);
+	assert!(stepped > 0.0 && stepped < 10.0);
+
+	// a slow signal passes through nearly unchanged
+	let settled = (2..=1000).fold(stepped, |_, i| bank.apply("WTPT", 100.0, i as f64 * 0.01));
+	assert!((settled - 100.0).abs() < 0.01);
+}
+
+#[test]
+fn filter_bank_rejects_invalid_filters() {
+	let filtered = |filter| {
+		let mut pt = mapping("WTPT", SensorType::Pt, None);
+		pt.filter = Some(filter);
+		FilterBank::new(&[pt])
+	};
+
+	for filter in [FilterConfig::MovingAverage { window: 0 }, FilterConfig::Median { window: 0 }] {
+		assert_eq!(filtered(filter).err(), Some(FilterError::EmptyWindow { mapping: "WTPT".to_owned() }));
+	}
+
+	for alpha in [0.0, -0.5, 1.5, f64::NAN] {
+		assert!(matches!(filtered(FilterConfig::Exponential { alpha }), Err(FilterError::InvalidAlpha { .. })));
+	}
+
+	for cutoff in [0.0, -1.0, f64::INFINITY, f64::NAN] {
+		assert!(matches!(filtered(FilterConfig::LowPass { cutoff }), Err(FilterError::InvalidCutoff { .. })));
+	}
+
+	assert!(filtered(FilterConfig::Exponential { alpha: 1.0 }).is_ok());
+	assert!(filtered(FilterConfig::MovingAverage { window: 1 }).is_ok());
+}
+
+#[test]
+fn filters_recover_from_non_finite_readings() {
+	let mut lc = mapping("LC1", SensorType::LoadCell, None);
+	lc.filter = Some(FilterConfig::MovingAverage { window: 2 });
+
+	let mut tc = mapping("TC1", SensorType::Tc, None);
+	tc.filter = Some(FilterConfig::Exponential { alpha: 0.5 });
+
+	let mut bank = FilterBank::new(&[lc, tc]).unwrap();
+
+	assert_eq!(bank.apply("LC1", 10.0, 0.0), 10.0);
+	assert!(bank.apply("LC1", f64::NAN, 1.0).is_nan());
+	assert_eq!(bank.apply("LC1", 20.0, 2.0), 15.0);
+	assert_eq!(bank.apply("LC1", 40.0, 3.0), 30.0);
+
+	assert_eq!(bank.apply("TC1", 300.0, 0.0), 300.0);
+	assert_eq!(bank.apply("TC1", f64::INFINITY, 1.0), f64::INFINITY);
+	assert_eq!(bank.apply("TC1", 310.0, 2.0), 305.0);
+}
+
+#[test]
+fn board_health_tracks_rails_and_brownouts() {
+	let limits = HealthLimits {
+		min_rail_voltage: 22.0,
+		max_rail_voltage: 26.0,
+		min_rail_current: 0.1,
+		max_rail_current: 3.0,
+		brownout_voltage: 18.0,
+		timeout: 1.0,
+		rate_window: 1.0,
+	};
+
+	let mut monitor = BoardHealthMonitor::new(limits);
+
+	let rail = |value: f64, channel_type: ChannelType| DataPoint { value, timestamp: 0.0, channel: 0, channel_type };
+
+	for (i, voltage) in [24.0, 17.5, 24.1, 16.0, 24.0].into_iter().enumerate() {
+		let data_points = vec![rail(voltage, ChannelType::RailVoltage), rail(1.2, ChannelType::RailCurrent)];
+		monitor.record(&DataMessage::Sam("sam-01".to_owned(), Cow::Owned(data_points)), i as f64 * 0.1);
+	}
+
+	monitor.record(&DataMessage::FlightHeartbeat, 0.5);
+
+	let health = monitor.health("sam-01", 0.4).unwrap();
+	assert_eq!(health.rail_voltage, Some(RailStatistics { latest: 24.0, min: 16.0, max: 24.1 }));
+	assert_eq!(health.brownout_count, 2);
+	assert!(!health.brownout);
+	assert!(health.rails_in_range);
+	assert!(health.online);
+	assert_eq!(health.packet_rate, 5.0);
+
+	let stale = monitor.health("sam-01", 5.0).unwrap();
+	assert!(!stale.online);
+	assert_eq!(stale.packet_rate, 0.0);
+
+	assert_eq!(monitor.report(0.4).len(), 1);
+	assert!(monitor.health("sam-02", 0.4).is_none());
+
+	// a dead rail drawing no current is out of range, as is one drawing too much
+	for (current, in_range) in [(0.0, false), (0.1, true), (3.5, false)] {
+		let data_points = vec![rail(24.0, ChannelType::RailVoltage), rail(current, ChannelType::RailCurrent)];
+		monitor.record(&DataMessage::Sam("sam-01".to_owned(), Cow::Owned(data_points)), 1.0);
+		assert_eq!(monitor.health("sam-01", 1.0).unwrap().rails_in_range, in_range, "{current} A");
+	}
+}
+
+#[test]
+fn board_health_without_a_rate_window_reports_no_packet_rate() {
+	for rate_window in [0.0, -1.0, f64::NAN] {
+		let mut monitor = BoardHealthMonitor::new(HealthLimits {
+			min_rail_voltage: 22.0,
+			max_rail_voltage: 26.0,
+			min_rail_current: 0.1,
+		max_rail_current: 3.0,
+			brownout_voltage: 18.0,
+			timeout: 1.0,
+			rate_window,
+		});
+
+		monitor.record(&DataMessage::Identity("sam-01".to_owned()), 2.0);
+		monitor.record(&DataMessage::Identity("sam-01".to_owned()), 2.5);
+
+		let health = monitor.health("sam-01", 2.5).unwrap();
+		assert_eq!(health.packet_rate, 0.0);
+		assert_eq!(health.last_seen, 2.5);
+		assert!(health.online);
+	}
+}
@@ -1,14 +1,954 @@
-use std::sync::{Arc, Mutex};
+mod support;
 
-use common::sequence;
-use pyo3::Python;
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Instant};
 
+use common::{
+	comm::{CompositeValveState, LogLevel, LogRecord, Measurement, Parameter, ParameterKind, ParameterValue, SensorType, SequenceError, SequenceStatus, Severity, Trigger, TriggerMode, Unit, ValveState},
+	sequence::{self, DeviceError, DeviceHandler, Namespace, OverrunAction, Sandbox, SensorSource, SequenceManager, SequenceRuntime, SimulatedClock, SimulatedSensor, SimulatedValve, Simulation, TriggerEngine, Watchdog},
+};
+use support::{add_rejecting_valve, add_sensor, add_valve, await_sleeping, initialize, mapping, poll, sequence, set_reading, trigger};
 
+#[test]
+fn test_interval() -> Result<(), SequenceError> {
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(Vec::new())));
+	let clock = SimulatedClock::auto_advancing();
+	runtime.set_clock(clock.clone());
+
+	runtime.run(sequence("interval", "for i in interval(3, 20 * ms):\n\tpass"))?;
+
+	// the first iteration is yielded immediately, so three iterations span two periods
+	assert_eq!(clock.elapsed(), std::time::Duration::from_millis(40));
+
+	Ok(())
+}
+
+#[test]
+fn run_reports_exceptions() {
+	initialize();
+
+	let Err(SequenceError::Exception(exception)) = sequence::run(sequence("error", "x = 1\ny = undefined_name")) else {
+		panic!("expected sequence to raise an exception");
+	};
+
+	assert_eq!(exception.exception_type, "NameError");
+	assert_eq!(exception.line, Some(2));
+	assert!(exception.traceback.is_some());
+
+	let Err(SequenceError::Exception(exception)) = sequence::run(sequence("syntax", "x = 1\n\nif x\n")) else {
+		panic!("expected sequence to fail to compile");
+	};
+
+	assert_eq!(exception.exception_type, "SyntaxError");
+	assert_eq!(exception.line, Some(3));
+}
+
+#[test]
+fn sensor_comparisons_compare_the_reading_with_the_other_operand() -> Result<(), SequenceError> {
+	set_reading("COMPARE_PT", 50.0);
+
+	let script = "\
+assert COMPARE_PT < 60 * psi and COMPARE_PT <= 50 * psi
+assert COMPARE_PT > 40 * psi and COMPARE_PT >= 50 * psi
+assert not COMPARE_PT > 60 * psi and not COMPARE_PT < 40 * psi
+assert COMPARE_PT == 50 * psi and COMPARE_PT != 60 * psi
+assert 60 * psi > COMPARE_PT and 40 * psi < COMPARE_PT
+assert not 60 * psi < COMPARE_PT and not 40 * psi > COMPARE_PT
+";
+
+	sequence::run(sequence("compare", script))
+}
+
+#[test]
+fn stop_interrupts_waits() {
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(Vec::new())));
+	let clock = SimulatedClock::new();
+	runtime.set_clock(clock.clone());
+
+	// the clock is never stepped, so each sequence can only end by being stopped
+	for script in ["while True:\n\twait_for(10 * s)", "for i in interval(1000, 1 * s):\n\tpass"] {
+		let handle = runtime.start(sequence("forever", script));
+		await_sleeping(&clock, 1);
+
+		handle.stop();
+		assert_eq!(handle.join(), Err(SequenceError::Stopped));
+	}
+
+	assert_eq!(clock.elapsed(), std::time::Duration::ZERO);
+}
+
+#[test]
+fn manager_runs_sequences_concurrently() {
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(Vec::new())));
+	let clock = SimulatedClock::new();
+	runtime.set_clock(clock.clone());
+
+	let manager = SequenceManager::with_runtime(runtime);
+	let script = "for i in interval(5, 40 * ms):\n\tpass";
+
+	manager.start(sequence("first", script)).unwrap();
+	manager.start(sequence("second", script)).unwrap();
+
+	assert_eq!(
+		manager.start(sequence("first", script)),
+		Err(SequenceError::AlreadyRunning("first".to_owned())),
+	);
+
+	// both sequences wait at once, and finish together once their four periods have passed
+	await_sleeping(&clock, 2);
+	clock.advance(std::time::Duration::from_millis(160));
+
+	assert_eq!(manager.wait("first"), Some(SequenceStatus::Finished));
+	assert_eq!(manager.wait("second"), Some(SequenceStatus::Finished));
+
+	manager.start(sequence("stopped", "wait_for(10 * s)")).unwrap();
+	manager.start(sequence("failed", "raise ValueError('bad')")).unwrap();
+
+	assert!(manager.stop("stopped"));
+	assert_eq!(manager.wait("stopped"), Some(SequenceStatus::Stopped));
+	assert!(matches!(manager.wait("failed"), Some(SequenceStatus::Failed(SequenceError::Exception(_)))));
+	assert!(!manager.stop("failed"));
+	assert!(manager.running().is_empty());
+}
+
+#[test]
+fn trigger_engine_runs_scripts_when_conditions_are_met() {
+	set_reading("TRIG_KTPT", 100.0);
+
+	let manager = SequenceManager::new();
+	let engine = TriggerEngine::new(manager.clone(), std::time::Duration::from_millis(10));
+
+	let trigger = Trigger {
+		name: "low_ktpt".to_owned(),
+		condition: "TRIG_KTPT < 90 * psi".to_owned(),
+		script: "pass".to_owned(),
+		active: true,
+		mode: TriggerMode::Level,
+		cooldown: None,
+		priority: 0,
+	};
+
+	engine.set(trigger.clone()).unwrap();
+	assert!(engine.poll().is_empty());
+
+	set_reading("TRIG_KTPT", 80.0);
+	assert_eq!(engine.poll(), vec!["low_ktpt".to_owned()]);
+	assert_eq!(manager.wait("low_ktpt"), Some(SequenceStatus::Finished));
+
+	assert!(engine.deactivate("low_ktpt"));
+	assert!(engine.poll().is_empty());
+
+	// replacing the trigger by name swaps out its condition
+	engine.set(Trigger { condition: "TRIG_KTPT > 90 * psi".to_owned(), ..trigger.clone() }).unwrap();
+	assert!(engine.poll().is_empty());
+
+	set_reading("TRIG_KTPT", 95.0);
+	assert_eq!(engine.poll(), vec!["low_ktpt".to_owned()]);
+
+	let invalid = Trigger { condition: "TRIG_KTPT <".to_owned(), ..trigger };
+	assert!(matches!(engine.set(invalid), Err(SequenceError::Exception(_))));
+	assert_eq!(engine.get("low_ktpt").unwrap().condition, "TRIG_KTPT > 90 * psi");
+}
+
+#[test]
+fn trigger_modes() {
+	set_reading("MODE_PT", 100.0);
+
+	let manager = SequenceManager::new();
+	let engine = TriggerEngine::new(manager.clone(), std::time::Duration::from_millis(10));
+
+	engine.set(trigger("edge", "MODE_PT < 90 * psi", TriggerMode::RisingEdge)).unwrap();
+	engine.set(trigger("level", "MODE_PT < 90 * psi", TriggerMode::Level)).unwrap();
+	engine.set(trigger("once", "MODE_PT < 90 * psi", TriggerMode::OneShot)).unwrap();
+
+	assert!(poll(&engine, &manager).is_empty());
+
+	set_reading("MODE_PT", 80.0);
+	assert_eq!(poll(&engine, &manager), ["edge", "level", "once"]);
+	assert_eq!(poll(&engine, &manager), ["level"]);
+	assert_eq!(poll(&engine, &manager), ["level"]);
+	assert!(!engine.get("once").unwrap().active);
+
+	set_reading("MODE_PT", 100.0);
+	assert!(poll(&engine, &manager).is_empty());
+
+	set_reading("MODE_PT", 80.0);
+	assert_eq!(poll(&engine, &manager), ["edge", "level"]);
+}
+
+#[test]
+fn trigger_cooldown_and_priority() {
+	let simulation = Simulation::new(3);
+
+	simulation.add_sensor("COOL_PT", SimulatedSensor {
+		source: SensorSource::Constant(80.0),
+		unit: Unit::Psi,
+		noise: 0.0,
+	});
+
+	// cooldowns are measured by the clock of the runtime, which only moves when stepped
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(vec![mapping("COOL_PT", SensorType::Pt)])));
+	let clock = SimulatedClock::new();
+
+	runtime.set_device_handler(simulation);
+	runtime.set_clock(clock.clone());
+
+	let manager = SequenceManager::with_runtime(runtime);
+	let engine = TriggerEngine::new(manager.clone(), std::time::Duration::from_millis(10));
+
+	let cooldown = std::time::Duration::from_millis(200);
+
+	engine.set(Trigger { cooldown: Some(cooldown), ..trigger("cooled", "COOL_PT < 90 * psi", TriggerMode::Level) }).unwrap();
+	engine.set(Trigger { priority: 10, ..trigger("urgent", "COOL_PT < 90 * psi", TriggerMode::Level) }).unwrap();
+	engine.set(Trigger { priority: -1, ..trigger("lazy", "COOL_PT < 90 * psi", TriggerMode::Level) }).unwrap();
+
+	assert_eq!(poll(&engine, &manager), ["urgent", "cooled", "lazy"]);
+	assert_eq!(poll(&engine, &manager), ["urgent", "lazy"]);
+
+	clock.advance(cooldown - std::time::Duration::from_millis(1));
+	assert_eq!(poll(&engine, &manager), ["urgent", "lazy"]);
+
+	clock.advance(std::time::Duration::from_millis(1));
+	assert_eq!(poll(&engine, &manager), ["urgent", "cooled", "lazy"]);
+}
+
+/// Deactivates a trigger of an engine whenever a sensor is read, as a device handler calling back
+/// into the engine from within a trigger condition.
+struct DeactivatingHandler(Arc<std::sync::OnceLock<TriggerEngine>>);
+
+impl DeviceHandler for DeactivatingHandler {
+	fn read_sensor(&self, _name: &str) -> Result<Measurement, DeviceError> {
+		if let Some(engine) = self.0.get() {
+			engine.deactivate("deactivated");
+		}
+
+		Ok(Measurement { value: 50.0, unit: Unit::Psi })
+	}
+
+	fn read_valve_state(&self, name: &str) -> Result<CompositeValveState, DeviceError> {
+		Err(DeviceError::UnknownDevice(name.to_owned()))
+	}
+
+	fn actuate_valve(&self, name: &str, _state: ValveState) -> Result<(), DeviceError> {
+		Err(DeviceError::UnknownDevice(name.to_owned()))
+	}
+}
+
+#[test]
+fn trigger_conditions_may_call_back_into_the_engine() {
+	let engine = Arc::new(std::sync::OnceLock::new());
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(vec![mapping("CALLBACK_PT", SensorType::Pt)])));
+	runtime.set_device_handler(DeactivatingHandler(engine.clone()));
+
+	let manager = SequenceManager::with_runtime(runtime);
+	let _ = engine.set(TriggerEngine::new(manager.clone(), std::time::Duration::from_millis(10)));
+	let engine = engine.get().unwrap().clone();
+
+	engine.set(trigger("deactivated", "CALLBACK_PT < 90 * psi", TriggerMode::Level)).unwrap();
+	engine.set(trigger("aborting", "abort()", TriggerMode::Level)).unwrap();
+
+	// polling would never finish if the engine were locked while conditions are evaluated
+	let (sender, receiver) = std::sync::mpsc::channel();
+
+	std::thread::spawn({
+		let engine = engine.clone();
+		move || sender.send(engine.poll())
+	});
+
+	let started = receiver.recv_timeout(std::time::Duration::from_secs(5)).expect("polling deadlocked");
+
+	// the first trigger was deactivated by its own condition, and the second by the abort it requested
+	assert!(started.is_empty());
+	assert!(!engine.get("deactivated").unwrap().active);
+	assert!(!engine.get("aborting").unwrap().active);
+}
+
+#[test]
+fn validate_flags_unknown_names_and_unit_mismatches() -> Result<(), SequenceError> {
+	initialize();
+
+	let mappings = [
+		mapping("VALIDATE_PT", SensorType::Pt),
+		mapping("VALIDATE_TC", SensorType::Tc),
+		mapping("VALIDATE_VALVE", SensorType::Valve),
+	];
+
+	let valid = "limit = 500 * psi\nfor _ in interval(3, 10 * ms):\n\tif VALIDATE_PT > limit:\n\t\tVALIDATE_VALVE.open()\n\t\tabort()";
+	assert_eq!(sequence::validate(&sequence("valid", valid), &mappings)?, Vec::new());
+
+	let invalid = "wait_for(1 * s)\nif VALIDATE_PT > VALIDATE_TC:\n\tOTHER_VALVE.open()\nx = VALIDATE_PT.read() - 300 * K";
+	let diagnostics = sequence::validate(&sequence("invalid", invalid), &mappings)?;
+
+	let positions = diagnostics
+		.iter()
+		.map(|diagnostic| (diagnostic.line, diagnostic.column, diagnostic.severity))
+		.collect::<Vec<_>>();
+
+	assert_eq!(positions, [(2, 4, Severity::Warning), (3, 2, Severity::Error), (4, 5, Severity::Warning)]);
+	assert!(diagnostics[0].message.contains("pressure with temperature"));
+	assert!(diagnostics[1].message.contains("OTHER_VALVE"));
+
+	let diagnostics = sequence::validate(&sequence("syntax", "x = 1\nif x\n"), &mappings)?;
+	assert_eq!(diagnostics.len(), 1);
+	assert_eq!((diagnostics[0].line, diagnostics[0].severity), (2, Severity::Error));
+
+	Ok(())
+}
+
+#[test]
+fn validate_checks_virtual_sensor_units_and_reports_invalid_expressions() -> Result<(), SequenceError> {
+	initialize();
+
+	let mut virtual_dp = mapping("VALIDATE_DP", SensorType::Virtual);
+	virtual_dp.expression = Some("VALIDATE_PT - VALIDATE_PT".to_owned());
+
+	let mut mappings = vec![mapping("VALIDATE_PT", SensorType::Pt), virtual_dp];
+	let script = "if VALIDATE_DP > 10 * K:\n\tpass";
+
+	let diagnostics = sequence::validate(&sequence("virtual", script), &mappings)?;
+	assert_eq!(diagnostics.len(), 1);
+	assert_eq!((diagnostics[0].line, diagnostics[0].severity), (1, Severity::Warning));
+
+	// one bad expression leaves the units of every virtual sensor unknown, which is reported
+	let mut broken = mapping("VALIDATE_BROKEN", SensorType::Virtual);
+	broken.expression = Some("VALIDATE_PT +".to_owned());
+	mappings.push(broken);
+
+	let diagnostics = sequence::validate(&sequence("virtual", script), &mappings)?;
+	assert_eq!(diagnostics.len(), 1);
+	assert_eq!((diagnostics[0].line, diagnostics[0].column, diagnostics[0].severity), (0, 0, Severity::Warning));
+	assert!(diagnostics[0].message.contains("syntax error"), "{}", diagnostics[0].message);
+
+	Ok(())
+}
+
+#[test]
+fn valve_state_is_typed() -> Result<(), SequenceError> {
+	add_valve("STATE_V");
+
+	let script = "\
+assert STATE_V.actual == ValveState.Undetermined and STATE_V.is_open() is False
+STATE_V.open()
+assert STATE_V.commanded == ValveState.Open and STATE_V.actual == ValveState.Open
+assert STATE_V.is_open() and not STATE_V.is_closed()
+STATE_V.close()
+state = STATE_V.state
+assert isinstance(state, CompositeValveState)
+assert state.commanded == ValveState.Closed and state.actual != ValveState.Open";
+
+	sequence::run(sequence("valve_state", script))
+}
+
+#[test]
+fn device_errors_raise_specific_exceptions() -> Result<(), SequenceError> {
+	add_rejecting_valve("REJECTING_V");
+	add_sensor("SILENT_PT");
+
+	let script = "\
+try:
+	REJECTING_V.open()
+	assert False
+except ActuationRejectedError as error:
+	assert 'REJECTING_V' in str(error)
+
+try:
+	SILENT_PT > 10 * psi
+	assert False
+except DeviceUnavailableError:
+	pass
+
+try:
+	Sensor('NOT_MAPPED').read()
+	assert False
+except UnknownDeviceError:
+	pass
+
+try:
+	Valve('NOT_MAPPED').close()
+	assert False
+except DeviceError:
+	pass";
+
+	sequence::run(sequence("device_errors", script))?;
+
+	let Err(SequenceError::Exception(exception)) = sequence::run(sequence("uncaught", "Sensor('NOT_MAPPED').read()")) else {
+		panic!("expected an unknown device to raise an exception");
+	};
+
+	assert_eq!(exception.exception_type, "UnknownDeviceError");
+	assert!(exception.message.contains("NOT_MAPPED"));
+
+	Ok(())
+}
+
+#[test]
+fn runtimes_are_independent() {
+	let runtime = |pressure: f64| {
+		let simulation = Simulation::new(42);
+
+		simulation.add_sensor("RUNTIME_PT", SimulatedSensor {
+			source: SensorSource::Constant(pressure),
+			unit: Unit::Psi,
+			noise: 0.0,
+		});
+
+		let runtime = SequenceRuntime::new(Arc::new(Mutex::new(vec![mapping("RUNTIME_PT", SensorType::Pt)])));
+		runtime.set_device_handler(simulation);
+		runtime
+	};
+
+	let (low, high) = (runtime(10.0), runtime(1000.0));
+
+	let low_handle = low.start(sequence("low", "assert RUNTIME_PT < 100 * psi"));
+	let high_handle = high.start(sequence("high", "assert RUNTIME_PT > 100 * psi"));
+
+	assert_eq!(low_handle.join(), Ok(()));
+	assert_eq!(high_handle.join(), Ok(()));
+
+	// each runtime keeps its own persistent namespace
+	assert_eq!(low.run_in(sequence("low", "marker = 'low'"), Namespace::Persistent), Ok(()));
+	assert_eq!(high.run_in(sequence("high", "marker = 'high'"), Namespace::Persistent), Ok(()));
+	assert_eq!(low.run_in(sequence("low_marker", "assert marker == 'low'"), Namespace::Persistent), Ok(()));
+	assert_eq!(high.run_in(sequence("high_marker", "assert marker == 'high'"), Namespace::Persistent), Ok(()));
+
+	// managers and trigger engines evaluate and run in the runtime they were given
+	let manager = SequenceManager::with_runtime(high.clone());
+	let engine = TriggerEngine::new(manager.clone(), std::time::Duration::from_millis(10));
+	engine.set(trigger("runtime_trigger", "RUNTIME_PT > 500 * psi", TriggerMode::RisingEdge)).unwrap();
+
+	assert_eq!(poll(&engine, &manager), ["runtime_trigger"]);
+	assert!(manager.runtime().ptr_eq(&high));
+}
+
+#[test]
+fn runs_are_isolated_unless_persistent() {
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(vec![mapping("ISOLATED_PT", SensorType::Pt)])));
+
+	assert_eq!(runtime.run(sequence("leave", "leftover = 1\nISOLATED_PT = None\npsi = None")), Ok(()));
+
+	// a later run neither sees leftover globals nor inherits shadowed mappings and library names
+	let check = "assert 'leftover' not in globals()\nassert isinstance(ISOLATED_PT, Sensor)\nassert psi is not None";
+	assert_eq!(runtime.run(sequence("check", check)), Ok(()));
+
+	assert_eq!(runtime.run_in(sequence("keep", "kept = 1"), Namespace::Persistent), Ok(()));
+	assert_eq!(runtime.run_in(sequence("kept", "assert kept == 1"), Namespace::Persistent), Ok(()));
+	assert_eq!(runtime.run(sequence("not_kept", "assert 'kept' not in globals()")), Ok(()));
+}
+
+#[test]
+fn mappings_reload_into_running_sequences() {
+	let simulation = Simulation::new(7);
+
+	for name in ["RELOAD_OLD", "RELOAD_NEW"] {
+		simulation.add_sensor(name, SimulatedSensor {
+			source: SensorSource::Constant(100.0),
+			unit: Unit::Psi,
+			noise: 0.0,
+		});
+	}
+
+	simulation.add_valve("RELOAD_V", SimulatedValve {
+		initial: ValveState::Closed,
+		actuation_delay: std::time::Duration::ZERO,
+	});
+
+	let mappings = Arc::new(Mutex::new(vec![mapping("RELOAD_OLD", SensorType::Pt), mapping("RELOAD_V", SensorType::Valve)]));
+	let runtime = SequenceRuntime::new(mappings.clone());
+	runtime.set_device_handler(simulation.clone());
+
+	let script = "\
+old = RELOAD_OLD
+assert old.read() == 100 * psi
+RELOAD_V.open()
+
+while 'RELOAD_NEW' not in globals():
+	wait_for(1 * ms)
+
+assert RELOAD_NEW.read() == 100 * psi
+
+try:
+	old.read()
+	assert False, 'removed sensor was read'
+except DeviceRemovedError as error:
+	assert 'RELOAD_OLD' in str(error)
+";
+
+	let handle = runtime.start(sequence("reload", script));
+
+	// wait for the sequence to hold the old sensor before it is removed
+	let started = Instant::now();
+
+	while simulation.commanded("RELOAD_V") != Some(ValveState::Open) {
+		assert!(started.elapsed() < std::time::Duration::from_secs(5), "sequence did not start");
+		std::thread::sleep(std::time::Duration::from_millis(1));
+	}
+
+	*mappings.lock().unwrap() = vec![mapping("RELOAD_NEW", SensorType::Pt), mapping("RELOAD_V", SensorType::Valve)];
+	runtime.reload_mappings();
+
+	assert_eq!(handle.join(), Ok(()));
+}
 
 #[test]
-fn test_interval() {
-	let mappings = Arc::new(Mutex::new(Vec::new()));
-	sequence::initialize(mappings);
+fn sandbox_blocks_unsafe_builtins_imports_and_attributes() {
+	let simulation = Simulation::new(3);
+
+	simulation.add_sensor("SANDBOX_PT", SimulatedSensor {
+		source: SensorSource::Constant(50.0),
+		unit: Unit::Psi,
+		noise: 0.0,
+	});
+
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(vec![mapping("SANDBOX_PT", SensorType::Pt)])));
+	runtime.set_device_handler(simulation);
+	runtime.set_sandbox(Some(Sandbox::default()));
+
+	let allowed = "\
+import math
+from itertools import count
+
+assert SANDBOX_PT.read() == 50 * psi
+assert math.sqrt(100) == 10
+assert sorted([next(count(2)), 1]) == [1, 2]
+
+try:
+	open('/etc/hostname')
+	assert False
+except SandboxError:
+	pass
+";
+
+	assert_eq!(runtime.run(sequence("allowed", allowed)), Ok(()));
+
+	for (script, message) in [
+		("import os", "importing module 'os'"),
+		("from subprocess import run", "importing module 'subprocess'"),
+		("open('/etc/hostname')", "'open'"),
+		("eval('1')", "'eval'"),
+		("x = 1\nx.__class__", "attribute '__class__' on line 2"),
+	] {
+		let Err(SequenceError::Exception(exception)) = runtime.run(sequence("blocked", script)) else {
+			panic!("expected '{script}' to be blocked");
+		};
+
+		assert_eq!(exception.exception_type, "SandboxError");
+		assert!(exception.message.contains(message), "unexpected message: {}", exception.message);
+	}
+
+	// attributes looked up by a string or by a class pattern cannot be used to escape the sandbox
+	let marker = std::env::temp_dir().join(format!("sandbox-escape-{}", std::process::id()));
+
+	let by_string = format!("\
+import operator
+base = operator.attrgetter('__class__.__base__')(1)
+wrap_close = [c for c in operator.methodcaller('__subclasses__')(base) if str(c).endswith('_wrap_close\\'>')][0]
+operator.attrgetter('__init__.__globals__')(wrap_close)['system']('echo escaped > {}')
+", marker.display());
+
+	let by_pattern = format!("\
+match 1:
+	case object(__class__=cls):
+		pass
+
+match cls:
+	case object(__base__=base):
+		pass
+
+match base:
+	case object(__subclasses__=subclasses):
+		pass
+
+wrap_close = [c for c in subclasses() if str(c).endswith('_wrap_close\\'>')][0]
+
+match wrap_close:
+	case object(__init__=init):
+		pass
+
+match init:
+	case object(__globals__=globals):
+		globals['system']('echo escaped > {}')
+", marker.display());
+
+	for (script, message) in [(by_string, "importing module 'operator'"), (by_pattern, "attribute '__class__' on line 2")] {
+		let Err(SequenceError::Exception(exception)) = runtime.run(sequence("escape", &script)) else {
+			panic!("expected the escape to be blocked");
+		};
+
+		assert_eq!(exception.exception_type, "SandboxError");
+		assert!(exception.message.contains(message), "unexpected message: {}", exception.message);
+	}
+
+	assert!(!marker.exists());
+
+	runtime.set_sandbox(None);
+	assert_eq!(runtime.run(sequence("unsandboxed", "import os")), Ok(()));
+}
+
+#[test]
+fn watchdog_interrupts_sequences_and_triggers_which_overrun() {
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(Vec::new())));
+	let budget = std::time::Duration::from_millis(100);
+
+	runtime.set_watchdog(Watchdog {
+		sequence_budget: Some(budget),
+		trigger_budget: Some(budget),
+		action: OverrunAction::Stop,
+	});
+
+	// a busy loop is interrupted even if it swallows every exception
+	let busy = "while True:\n\ttry:\n\t\tpass\n\texcept BaseException:\n\t\tpass";
+	let started = Instant::now();
+
+	assert_eq!(runtime.run(sequence("busy", "while True:\n\tpass")), Err(SequenceError::Overrun { budget }));
+	assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+	let handle = runtime.start(sequence("swallowing", busy));
+	assert_eq!(handle.join(), Err(SequenceError::Overrun { budget }));
+
+	// time spent waiting does not count against the budget
+	assert_eq!(runtime.run(sequence("waiting", "for _ in range(4):\n\twait_for(50 * ms)")), Ok(()));
+
+	// an overrunning trigger condition is treated as not met and its trigger deactivated
+	let manager = SequenceManager::with_runtime(runtime.clone());
+	let engine = TriggerEngine::new(manager.clone(), std::time::Duration::from_millis(10));
+	engine.set(trigger("busy_condition", "all(True for _ in iter(int, 1))", TriggerMode::Level)).unwrap();
+
+	assert!(poll(&engine, &manager).is_empty());
+	assert!(!engine.get("busy_condition").unwrap().active);
+
+	// overruns only warn when configured to
+	runtime.set_watchdog(Watchdog {
+		sequence_budget: Some(std::time::Duration::from_millis(20)),
+		trigger_budget: None,
+		action: OverrunAction::Warn,
+	});
+
+	let slow = "deadline = 0\nfor i in range(10 ** 6):\n\tdeadline += i";
+	assert_eq!(runtime.run(sequence("slow", slow)), Ok(()));
+}
+
+#[test]
+fn watchdog_interruptions_never_outlive_their_sequence() {
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(Vec::new())));
+	let budget = std::time::Duration::from_millis(20);
+	let failures = Arc::new(Mutex::new(Vec::new()));
+
+	runtime.set_watchdog(Watchdog {
+		sequence_budget: Some(budget),
+		trigger_budget: None,
+		action: OverrunAction::Stop,
+	});
+
+	// the handler receives the partial line of output left by each sequence once its script has
+	// returned, and takes longer than the budget before running Python code, which is where an
+	// interruption still pending from the sequence would be raised.
+	runtime.set_log_handler({
+		let failures = failures.clone();
+
+		move |record: LogRecord| {
+			let result = pyo3::Python::with_gil(|py| {
+				py.allow_threads(|| std::thread::sleep(budget * 2));
+				py.run("sum(range(100))", None, None)
+			});
+
+			if let Err(error) = result {
+				failures.lock().unwrap().push(format!("{}: {error}", record.sequence));
+			}
+		}
+	});
+
+	assert_eq!(runtime.run(sequence("quick", "print('done', end='')")), Ok(()));
+
+	// the budget expires just before, while and just after each of these sequences ends
+	for offset in -5..=5 {
+		let end = budget.as_secs_f64() + f64::from(offset) / 1000.0;
+		let script = format!("import time\nend = time.perf_counter() + {end}\nwhile time.perf_counter() < end:\n\tpass\nprint('done', end='')");
+		let result = runtime.run(sequence(&format!("ending_{offset}"), &script));
+
+		assert!(matches!(result, Ok(()) | Err(SequenceError::Overrun { .. })), "unexpected result: {result:?}");
+		assert_eq!(runtime.run(sequence("after", "sum(range(100))")), Ok(()));
+	}
+
+	assert_eq!(*failures.lock().unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn output_and_logs_are_delivered_per_sequence() {
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(Vec::new())));
+	let records = Arc::new(Mutex::new(Vec::new()));
+
+	runtime.set_log_handler({
+		let records = records.clone();
+		move |record: LogRecord| records.lock().unwrap().push(record)
+	});
+
+	let script = |name: &str| format!("\
+import sys
+
+# the streams are replaced for the whole process, so anything else about them must still work
+for stream, original in [(sys.stdout, sys.__stdout__), (sys.stderr, sys.__stderr__)]:
+	assert stream is not original and stream.encoding == original.encoding and stream.errors == original.errors
+	assert stream.isatty() == original.isatty() and stream.fileno() == original.fileno()
+	assert stream.buffer is original.buffer
+
+print('started', '{name}')
+log(LogLevel.Warning, 'careful')
+wait_for(20 * ms)
+sys.stderr.write('failed\\nagain\\n')
+print('unfinished', end='')
+");
+
+	let first = runtime.start(sequence("first", &script("first")));
+	let second = runtime.start(sequence("second", &script("second")));
+
+	assert_eq!(first.join(), Ok(()));
+	assert_eq!(second.join(), Ok(()));
+
+	let records = records.lock().unwrap();
+
+	for name in ["first", "second"] {
+		let logged = records
+			.iter()
+			.filter(|record| record.sequence == name)
+			.map(|record| (record.level, record.message.as_str()))
+			.collect::<Vec<_>>();
+
+		let started = format!("started {name}");
+
+		assert_eq!(logged, [
+			(LogLevel::Info, started.as_str()),
+			(LogLevel::Warning, "careful"),
+			(LogLevel::Error, "failed"),
+			(LogLevel::Error, "again"),
+			(LogLevel::Info, "unfinished"),
+		]);
+	}
+
+	assert!(records.iter().all(|record| record.timestamp > 0.0));
+}
+
+#[test]
+fn parameters_are_validated_and_injected() {
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(Vec::new())));
+
+	let mut fill = sequence("fill", "\
+assert isinstance(target, Pressure) and target == 450 * psi
+assert cycles == 3 and isinstance(cycles, int)
+assert margin == 2.0 and vent is False and label == 'fuel'
+");
+
+	let parameter = |name: &str, kind: ParameterKind, default: Option<ParameterValue>| Parameter {
+		name: name.to_owned(),
+		kind,
+		default,
+	};
+
+	fill.parameters = vec![
+		parameter("target", ParameterKind::Quantity(Unit::Psi), None),
+		parameter("cycles", ParameterKind::Integer, Some(ParameterValue::Integer(1))),
+		parameter("margin", ParameterKind::Number, Some(ParameterValue::Integer(2))),
+		parameter("vent", ParameterKind::Boolean, Some(ParameterValue::Boolean(false))),
+		parameter("label", ParameterKind::Text, Some(ParameterValue::Text("fuel".to_owned()))),
+	];
+
+	fill.arguments = HashMap::from([
+		("target".to_owned(), ParameterValue::Number(450.0)),
+		("cycles".to_owned(), ParameterValue::Integer(3)),
+	]);
+
+	assert_eq!(runtime.run(fill.clone()), Ok(()));
+
+	// parameters are known names to the validator, and quantities carry their unit
+	let mut checked = fill.clone();
+	checked.script = "assert target > 10 * K".to_owned();
+
+	let diagnostics = sequence::validate(&checked, &[]).unwrap();
+	assert_eq!(diagnostics.len(), 1);
+	assert_eq!(diagnostics[0].severity, Severity::Warning);
+
+	let invalid = |arguments: &[(&str, ParameterValue)]| {
+		let mut invalid = fill.clone();
+		invalid.arguments = arguments.iter().cloned().map(|(name, value)| (name.to_owned(), value)).collect();
+
+		match runtime.run(invalid) {
+			Err(SequenceError::InvalidArgument { parameter, .. }) => parameter,
+			result => panic!("expected an invalid argument, got {result:?}"),
+		}
+	};
+
+	assert_eq!(invalid(&[]), "target");
+	assert_eq!(invalid(&[("target", ParameterValue::Text("high".to_owned()))]), "target");
+	assert_eq!(ParameterKind::Quantity(Unit::Psi).to_string(), "quantity in psi");
+	assert_eq!(invalid(&[("target", ParameterValue::Number(450.0)), ("cycles", ParameterValue::Number(1.5))]), "cycles");
+	assert_eq!(invalid(&[("target", ParameterValue::Number(450.0)), ("unknown", ParameterValue::Integer(1))]), "unknown");
+
+	// names which the script could not refer to, or which would hide another name, are rejected
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(vec![mapping("PARAM_PT", SensorType::Pt)])));
+
+	let rejected = [
+		("1st", "identifier"),
+		("if", "keyword"),
+		("None", "keyword"),
+		("class", "keyword"),
+		("wait_for", "sequences library"),
+		("psi", "sequences library"),
+		("len", "builtin"),
+		("PARAM_PT", "mapping"),
+	];
+
+	for (name, expected) in rejected {
+		let mut named = sequence("named", "pass");
+		named.parameters = vec![parameter(name, ParameterKind::Integer, Some(ParameterValue::Integer(1)))];
+
+		match runtime.run(named) {
+			Err(SequenceError::InvalidArgument { parameter, reason }) => {
+				assert_eq!(parameter, name);
+				assert!(reason.contains(expected), "'{name}' rejected because {reason}");
+			},
+			result => panic!("expected '{name}' to be rejected, got {result:?}"),
+		}
+	}
+}
+
+#[test]
+fn sensor_history_statistics() {
+	let simulation = Simulation::new(5);
+
+	for (name, value) in [("HIST_PT", 250.0), ("FRESH_PT", 10.0)] {
+		simulation.add_sensor(name, SimulatedSensor {
+			source: SensorSource::Constant(value),
+			unit: Unit::Psi,
+			noise: 0.0,
+		});
+	}
+
+	let mappings = vec![mapping("HIST_PT", SensorType::Pt), mapping("FRESH_PT", SensorType::Pt)];
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(mappings)));
+	let clock = SimulatedClock::new();
+
+	runtime.set_device_handler(simulation);
+	runtime.set_clock(clock.clone());
+
+	// the vehicle feeds a reading every second, each of which is held until the next
+	for (index, value) in [100.0, 150.0, 200.0, 250.0].into_iter().enumerate() {
+		if index > 0 {
+			clock.advance(std::time::Duration::from_secs(1));
+		}
+
+		runtime.record_reading("HIST_PT", &Measurement { value, unit: Unit::Psi });
+	}
+
+	let script = "\
+assert HIST_PT.max(10 * s) == 250 * psi
+assert HIST_PT.min(10 * s) == 100 * psi
+assert HIST_PT.min(1.5 * s) == 150 * psi
+assert HIST_PT.average(2 * s) == 175 * psi
+assert 49.9 * psi < HIST_PT.rate(3 * s) < 50.1 * psi
+assert 49.9 * psi < HIST_PT.rate() < 50.1 * psi
+
+assert HIST_PT.held_above(90 * psi, 3 * s)
+assert HIST_PT.held_above(120 * psi, 2 * s)
+assert not HIST_PT.held_above(120 * psi, 3 * s)
+assert not HIST_PT.held_above(0 * psi, 60 * s)
+
+# a sensor only ever read now has no history to speak of
+assert FRESH_PT.average(10 * s) == 10 * psi
+
+try:
+	FRESH_PT.rate()
+	assert False, 'rate computed from a single instant'
+except ValueError:
+	pass
+";
+
+	assert_eq!(runtime.run(sequence("history", script)), Ok(()));
+}
+
+#[test]
+fn sensor_history_statistics_only_use_the_latest_unit() {
+	let simulation = Simulation::new(5);
+
+	simulation.add_sensor("REMAPPED_TC", SimulatedSensor {
+		source: SensorSource::Constant(310.0),
+		unit: Unit::Kelvin,
+		noise: 0.0,
+	});
+
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(vec![mapping("REMAPPED_TC", SensorType::Tc)])));
+	let clock = SimulatedClock::new();
+
+	runtime.set_device_handler(simulation);
+	runtime.set_clock(clock.clone());
+
+	// the sensor was remapped after two seconds, so its earlier readings are in another unit
+	let readings = [(100.0, Unit::Psi), (200.0, Unit::Psi), (300.0, Unit::Kelvin)];
+
+	for (index, (value, unit)) in readings.into_iter().enumerate() {
+		if index > 0 {
+			clock.advance(std::time::Duration::from_secs(1));
+		}
+
+		runtime.record_reading("REMAPPED_TC", &Measurement { value, unit });
+	}
+
+	clock.advance(std::time::Duration::from_secs(1));
+
+	let script = "\
+assert REMAPPED_TC.min(10 * s) == 300 * K
+assert REMAPPED_TC.max(10 * s) == 310 * K
+assert REMAPPED_TC.average(10 * s) == 300 * K
+assert 9.9 * K < REMAPPED_TC.rate(10 * s) < 10.1 * K
+assert not REMAPPED_TC.held_above(0 * K, 10 * s)
+assert REMAPPED_TC.held_above(0 * K, 0.5 * s)
+";
+
+	assert_eq!(runtime.run(sequence("remapped", script)), Ok(()));
+}
+
+#[test]
+fn verified_valve_actuations() {
+	let clock = SimulatedClock::auto_advancing();
+	let simulation = Simulation::with_clock(11, clock.clone());
+
+	for (name, delay) in [("SLOW_V", 200), ("FAST_V", 10), ("STUCK_V", 60_000)] {
+		simulation.add_valve(name, SimulatedValve {
+			initial: ValveState::Closed,
+			actuation_delay: std::time::Duration::from_millis(delay),
+		});
+	}
+
+	let mappings = ["SLOW_V", "FAST_V", "STUCK_V"].map(|name| mapping(name, SensorType::Valve));
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(mappings.to_vec())));
+
+	runtime.set_device_handler(simulation.clone());
+	runtime.set_clock(clock);
+
+	let script = "\
+SLOW_V.open(verify=True, timeout=1 * s)
+assert SLOW_V.is_open()
+
+try:
+	SLOW_V.close(verify=True, timeout=50 * ms)
+	assert False, 'slow valve closed in time'
+except ActuationTimeoutError as error:
+	assert isinstance(error, DeviceError) and 'SLOW_V' in str(error)
+
+FAST_V.open_for(100 * ms)
+assert FAST_V.is_closed()
+
+FAST_V.pulse(20 * ms, 30 * ms, 3)
+assert FAST_V.is_closed()
+
+# a valve which fails to open is still commanded closed
+try:
+	STUCK_V.open_for(1 * s, timeout=100 * ms)
+	assert False, 'stuck valve opened'
+except ActuationTimeoutError:
+	pass
+
+assert STUCK_V.commanded == ValveState.Closed
+";
 
-	let script = 
-}
\ No newline at end of file
+	assert_eq!(runtime.run(sequence("verified", script)), Ok(()));
+	assert_eq!(simulation.actuations("FAST_V"), Some(8));
+	assert_eq!(simulation.actuations("STUCK_V"), Some(2));
+}
@@ -0,0 +1,165 @@
+//! The simulated vehicle, driven by simulated clocks so that its physics are deterministic.
+
+mod support;
+
+use std::{collections::HashMap, sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
+
+use common::{
+	comm::{CompositeValveState, Measurement, SensorType, SequenceError, Unit, ValveState},
+	sequence::{DeviceError, DeviceHandler, SensorSource, SequenceRuntime, SimulatedClock, SimulatedSensor, SimulatedTank, SimulatedValve, Simulation},
+};
+use support::{mapping, sequence};
+
+fn tank(pressure: f64, leak_rate: f64, valve: &str, fill_rate: f64) -> SimulatedTank {
+	SimulatedTank {
+		pressure,
+		max_pressure: 500.0,
+		leak_rate,
+		flows: vec![(valve.to_owned(), fill_rate)],
+	}
+}
+
+fn pressure_sensor(tank: &str, noise: f64) -> SimulatedSensor {
+	SimulatedSensor {
+		source: SensorSource::Tank(tank.to_owned()),
+		unit: Unit::Psi,
+		noise,
+	}
+}
+
+fn valve(actuation_delay: Duration) -> SimulatedValve {
+	SimulatedValve {
+		initial: ValveState::Closed,
+		actuation_delay,
+	}
+}
+
+/// Passes every device through to a simulation, counting how many times each sensor is read.
+struct CountingReads {
+	simulation: Simulation,
+	reads: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl DeviceHandler for CountingReads {
+	fn read_sensor(&self, name: &str) -> Result<Measurement, DeviceError> {
+		let measurement = self.simulation.read_sensor(name);
+		*self.reads.lock().unwrap().entry(name.to_owned()).or_default() += 1;
+		measurement
+	}
+
+	fn read_valve_state(&self, name: &str) -> Result<CompositeValveState, DeviceError> {
+		self.simulation.read_valve_state(name)
+	}
+
+	fn actuate_valve(&self, name: &str, state: ValveState) -> Result<(), DeviceError> {
+		self.simulation.actuate_valve(name, state)
+	}
+}
+
+#[test]
+fn hilo_holds_pressure_within_band() {
+	let clock = SimulatedClock::new();
+	let simulation = Simulation::with_clock(38, clock.clone());
+	simulation.add_tank("water", tank(200.0, 100.0, "BBV", 400.0));
+	simulation.add_tank("kerosene", tank(100.0, 50.0, "SWV", 300.0));
+	simulation.add_sensor("WTPT", pressure_sensor("water", 0.5));
+	simulation.add_sensor("KTPT", pressure_sensor("kerosene", 0.5));
+	simulation.add_valve("BBV", valve(Duration::from_millis(5)));
+	simulation.add_valve("SWV", valve(Duration::from_millis(5)));
+
+	let runtime = SequenceRuntime::new(Arc::new(Mutex::new(vec![
+		mapping("WTPT", SensorType::Pt),
+		mapping("KTPT", SensorType::Pt),
+		mapping("BBV", SensorType::Valve),
+		mapping("SWV", SensorType::Valve),
+	])));
+
+	let reads = Arc::new(Mutex::new(HashMap::new()));
+	runtime.set_device_handler(CountingReads { simulation: simulation.clone(), reads: reads.clone() });
+	runtime.set_clock(clock.clone());
+
+	let handle = runtime.start(sequence("hilo", include_str!("../hilo.py")));
+	let iterations = || reads.lock().unwrap().get("KTPT").copied().unwrap_or_default();
+
+	for _ in 0..50 {
+		clock.advance(Duration::from_millis(10));
+
+		// KTPT is read once at the end of each iteration, so two more reads mean that a whole
+		// iteration has seen the advanced simulation and acted on it
+		let advanced = iterations();
+		let started = Instant::now();
+
+		while iterations() < advanced + 2 {
+			assert!(started.elapsed() < Duration::from_secs(5), "hilo stopped iterating");
+			thread::sleep(Duration::from_millis(1));
+		}
+
+		let pressure = simulation.pressure("water").unwrap();
+		assert!((175.0..=225.0).contains(&pressure), "water pressure left band: {pressure}");
+	}
+
+	handle.stop();
+	assert_eq!(handle.join(), Err(SequenceError::Stopped));
+
+	// the bang-bang valve must have cycled to hold the band against the leak
+	assert!(simulation.actuations("BBV").unwrap() >= 4);
+	assert_eq!(simulation.actual("SWV"), Some(ValveState::Open));
+	assert!(simulation.pressure("kerosene").unwrap() > 90.0);
+}
+
+#[test]
+fn valves_lag_commands_and_sensors_are_noisy() {
+	// the simulation follows its own clock, no matter the clock of the runtime of the calling thread
+	let clock = SimulatedClock::new();
+	let simulation = Simulation::with_clock(7, clock.clone());
+	simulation.add_tank("tank", tank(100.0, 0.0, "FILL", 1000.0));
+	simulation.add_sensor("TANK_PT", pressure_sensor("tank", 2.0));
+	simulation.add_sensor("AMBIENT", SimulatedSensor { source: SensorSource::Constant(300.0), unit: Unit::Kelvin, noise: 0.0 });
+	simulation.add_valve("FILL", valve(Duration::from_millis(200)));
+
+	simulation.actuate_valve("FILL", ValveState::Open).unwrap();
+	assert_eq!(simulation.commanded("FILL"), Some(ValveState::Open));
+	assert_eq!(simulation.actual("FILL"), Some(ValveState::Closed));
+
+	// no flow until the valve has physically opened
+	clock.advance(Duration::from_millis(50));
+	assert_eq!(simulation.pressure("tank"), Some(100.0));
+
+	clock.advance(Duration::from_millis(150));
+	assert_eq!(simulation.actual("FILL"), Some(ValveState::Open));
+	assert_eq!(simulation.pressure("tank"), Some(100.0));
+
+	clock.advance(Duration::from_millis(100));
+	assert_eq!(simulation.pressure("tank"), Some(200.0));
+
+	simulation.actuate_valve("FILL", ValveState::Closed).unwrap();
+	clock.advance(Duration::from_millis(250));
+
+	assert_eq!(simulation.read_sensor("AMBIENT"), Ok(Measurement { value: 300.0, unit: Unit::Kelvin }));
+
+	let truth = simulation.pressure("tank").unwrap();
+
+	let readings = (0..200)
+		.map(|_| simulation.read_sensor("TANK_PT").unwrap().value)
+		.collect::<Vec<f64>>();
+
+	let mean = readings.iter().sum::<f64>() / readings.len() as f64;
+
+	assert!(readings.iter().any(|&reading| reading != truth));
+	assert!((mean - truth).abs() < 1.0, "mean {mean} strays from {truth}");
+
+	assert_eq!(
+		simulation.read_valve_state("FILL"),
+		Ok(CompositeValveState { commanded: ValveState::Closed, actual: ValveState::Closed }),
+	);
+
+	assert_eq!(simulation.read_sensor("MISSING"), Err(DeviceError::UnknownDevice("MISSING".to_owned())));
+
+	// an offline valve can neither be read nor actuated
+	simulation.set_offline("FILL", true);
+	assert_eq!(simulation.actuate_valve("FILL", ValveState::Open), Err(DeviceError::Offline("FILL".to_owned())));
+	assert_eq!(simulation.commanded("FILL"), Some(ValveState::Closed));
+
+	simulation.set_offline("FILL", false);
+	assert!(simulation.read_valve_state("FILL").is_ok());
+}
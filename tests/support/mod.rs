@@ -0,0 +1,210 @@
+#![allow(dead_code)]
+
+use std::{collections::HashMap, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex, Once, OnceLock}, thread, time::{Duration, Instant}};
+
+use common::{
+	comm::{CompositeValveState, Computer, Measurement, NodeMapping, SensorType, Sequence, Trigger, TriggerMode, Unit, ValveState},
+	sequence::{self, DeviceError, DeviceHandler, SequenceManager, SimulatedClock, TriggerEngine},
+};
+
+/// The mappings given to the sequences library, shared so tests can add the devices they use.
+static MAPPINGS: OnceLock<Arc<Mutex<Vec<NodeMapping>>>> = OnceLock::new();
+
+/// The pressure read from each mocked sensor. Tests use distinct device names since they run in parallel.
+static READINGS: Mutex<Option<HashMap<String, f64>>> = Mutex::new(None);
+
+/// Every valve actuation commanded through the mocked device handler, in order.
+static ACTUATIONS: Mutex<Vec<(String, ValveState)>> = Mutex::new(Vec::new());
+
+/// How many times the mocked device handler has been told of an abort.
+static ABORTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Valves whose actuations are rejected by the mocked device handler.
+static REJECTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Determines if a device with the given name and type has been mapped.
+fn is_mapped(name: &str, valve: bool) -> bool {
+	MAPPINGS
+		.get()
+		.is_some_and(|mappings| {
+			mappings
+				.lock()
+				.unwrap()
+				.iter()
+				.any(|mapping| mapping.text_id == name && (mapping.sensor_type == SensorType::Valve) == valve)
+		})
+}
+
+/// Serves readings from `READINGS` and records actuations in `ACTUATIONS`.
+struct MockHandler;
+
+impl DeviceHandler for MockHandler {
+	fn read_sensor(&self, name: &str) -> Result<Measurement, DeviceError> {
+		if !is_mapped(name, false) {
+			return Err(DeviceError::UnknownDevice(name.to_owned()));
+		}
+
+		READINGS
+			.lock()
+			.unwrap()
+			.as_ref()
+			.and_then(|readings| readings.get(name).copied())
+			.map(|value| Measurement { value, unit: Unit::Psi })
+			.ok_or_else(|| DeviceError::Unavailable(name.to_owned()))
+	}
+
+	fn read_valve_state(&self, name: &str) -> Result<CompositeValveState, DeviceError> {
+		if !is_mapped(name, true) {
+			return Err(DeviceError::UnknownDevice(name.to_owned()));
+		}
+
+		// mocked valves move instantly, so their actual state is whatever they were last commanded to
+		let state = ACTUATIONS
+			.lock()
+			.unwrap()
+			.iter()
+			.rfind(|(valve, _)| valve == name)
+			.map_or(ValveState::Undetermined, |&(_, state)| state);
+
+		Ok(CompositeValveState { commanded: state, actual: state })
+	}
+
+	fn actuate_valve(&self, name: &str, state: ValveState) -> Result<(), DeviceError> {
+		if !is_mapped(name, true) {
+			return Err(DeviceError::UnknownDevice(name.to_owned()));
+		}
+
+		if REJECTED.lock().unwrap().iter().any(|valve| valve == name) {
+			return Err(DeviceError::ActuationRejected {
+				valve: name.to_owned(),
+				reason: "mocked rejection".to_owned(),
+			});
+		}
+
+		ACTUATIONS.lock().unwrap().push((name.to_owned(), state));
+		Ok(())
+	}
+
+	fn abort(&self) {
+		ABORTS.fetch_add(1, Ordering::SeqCst);
+	}
+}
+
+/// Initializes the sequences library with a mocked device handler, exactly once per test binary.
+pub fn initialize() {
+	static INITIALIZE: Once = Once::new();
+
+	INITIALIZE.call_once(|| {
+		let mappings = MAPPINGS.get_or_init(|| Arc::new(Mutex::new(Vec::new())));
+		sequence::initialize(mappings.clone());
+		sequence::set_device_handler(MockHandler);
+	});
+}
+
+/// Constructs a mapping for a device with the given name, on an arbitrary board and channel.
+pub fn mapping(name: &str, sensor_type: SensorType) -> NodeMapping {
+	NodeMapping {
+		text_id: name.to_owned(),
+		board_id: "sam-01".to_owned(),
+		sensor_type,
+		channel: 0,
+		computer: Computer::Flight,
+		max: None,
+		min: None,
+		calibrated_offset: 0.0,
+		powered_threshold: None,
+		normally_closed: None,
+		expression: None,
+		filter: None,
+	}
+}
+
+fn add_mapping(name: &str, sensor_type: SensorType) {
+	initialize();
+
+	let mut mappings = MAPPINGS.get().unwrap().lock().unwrap();
+
+	if !mappings.iter().any(|mapping| mapping.text_id == name) {
+		mappings.push(mapping(name, sensor_type));
+	}
+}
+
+/// Maps a mocked pressure transducer with the given name, if not already mapped, and sets its reading.
+pub fn set_reading(name: &str, value: f64) {
+	add_mapping(name, SensorType::Pt);
+	READINGS.lock().unwrap().get_or_insert_with(HashMap::new).insert(name.to_owned(), value);
+}
+
+/// Maps a mocked pressure transducer with the given name, if not already mapped, without a reading.
+pub fn add_sensor(name: &str) {
+	add_mapping(name, SensorType::Pt);
+}
+
+/// Maps a mocked valve with the given name, if not already mapped.
+pub fn add_valve(name: &str) {
+	add_mapping(name, SensorType::Valve);
+}
+
+/// Maps a mocked valve with the given name, if not already mapped, whose actuations are always rejected.
+pub fn add_rejecting_valve(name: &str) {
+	add_valve(name);
+	REJECTED.lock().unwrap().push(name.to_owned());
+}
+
+/// Gets how many times the mocked device handler has been told of an abort.
+pub fn aborts() -> usize {
+	ABORTS.load(Ordering::SeqCst)
+}
+
+/// Gets every actuation commanded to the valve with the given name.
+pub fn actuations(name: &str) -> Vec<ValveState> {
+	ACTUATIONS
+		.lock()
+		.unwrap()
+		.iter()
+		.filter(|(valve, _)| valve == name)
+		.map(|(_, state)| *state)
+		.collect()
+}
+
+pub fn sequence(name: &str, script: &str) -> Sequence {
+	Sequence {
+		name: name.to_owned(),
+		script: script.to_owned(),
+		parameters: Vec::new(),
+		arguments: HashMap::new(),
+	}
+}
+
+pub fn trigger(name: &str, condition: &str, mode: TriggerMode) -> Trigger {
+	Trigger {
+		name: name.to_owned(),
+		condition: condition.to_owned(),
+		script: "pass".to_owned(),
+		active: true,
+		mode,
+		cooldown: None,
+		priority: 0,
+	}
+}
+
+/// Polls the engine after waiting for any trigger scripts from the previous poll to finish,
+/// so that a still-running script never prevents a firing.
+pub fn poll(engine: &TriggerEngine, manager: &SequenceManager) -> Vec<String> {
+	for name in manager.running() {
+		manager.wait(&name);
+	}
+
+	engine.poll()
+}
+
+/// Waits until the given number of threads are sleeping on the clock, so that it is only stepped once
+/// the sequences under test have reached their waits.
+pub fn await_sleeping(clock: &SimulatedClock, count: usize) {
+	let started = Instant::now();
+
+	while clock.sleeping() < count {
+		assert!(started.elapsed() < Duration::from_secs(5), "{count} sleeps never began");
+		thread::sleep(Duration::from_millis(1));
+	}
+}